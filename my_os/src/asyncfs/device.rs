@@ -0,0 +1,78 @@
+//! Storage devices completing I/O asynchronously.
+
+use crate::error::FsError;
+use crate::filesystem::StorageDevice;
+use core::future::{ready, Future};
+
+/// A storage device whose transfers complete through futures.
+///
+/// A driver can start a transfer, return `Pending`, and wake the task
+/// from its completion interrupt. Offsets are in bytes, as in
+/// `StorageDevice`.
+pub trait AsyncStorageDevice {
+    fn read<'a>(
+        &'a self,
+        offset: u64,
+        buffer: &'a mut [u8],
+    ) -> impl Future<Output = Result<(), FsError>> + 'a;
+
+    fn write<'a>(
+        &'a self,
+        offset: u64,
+        buffer: &'a [u8],
+    ) -> impl Future<Output = Result<(), FsError>> + 'a;
+
+    /// Flush buffered writes to the underlying medium.
+    fn flush(&self) -> impl Future<Output = Result<(), FsError>> + '_ {
+        ready(Ok(()))
+    }
+}
+
+// Borrowed devices, e.g. one a driver keeps a handle to for its interrupts.
+impl<D: AsyncStorageDevice + ?Sized> AsyncStorageDevice for &D {
+    fn read<'a>(
+        &'a self,
+        offset: u64,
+        buffer: &'a mut [u8],
+    ) -> impl Future<Output = Result<(), FsError>> + 'a {
+        (**self).read(offset, buffer)
+    }
+
+    fn write<'a>(
+        &'a self,
+        offset: u64,
+        buffer: &'a [u8],
+    ) -> impl Future<Output = Result<(), FsError>> + 'a {
+        (**self).write(offset, buffer)
+    }
+
+    fn flush(&self) -> impl Future<Output = Result<(), FsError>> + '_ {
+        (**self).flush()
+    }
+}
+
+/// Adapter running a synchronous device behind the async interface.
+/// Every future is ready on its first poll.
+pub struct Blocking<S: StorageDevice>(pub S);
+
+impl<S: StorageDevice> AsyncStorageDevice for Blocking<S> {
+    fn read<'a>(
+        &'a self,
+        offset: u64,
+        buffer: &'a mut [u8],
+    ) -> impl Future<Output = Result<(), FsError>> + 'a {
+        ready(self.0.read(offset, buffer))
+    }
+
+    fn write<'a>(
+        &'a self,
+        offset: u64,
+        buffer: &'a [u8],
+    ) -> impl Future<Output = Result<(), FsError>> + 'a {
+        ready(self.0.write(offset, buffer))
+    }
+
+    fn flush(&self) -> impl Future<Output = Result<(), FsError>> + '_ {
+        ready(self.0.flush())
+    }
+}
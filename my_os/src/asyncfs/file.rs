@@ -0,0 +1,209 @@
+//! Files on an asynchronous volume.
+//!
+//! Writes follow the order of `crate::file`: allocate and link clusters,
+//! write the data, then update the entry.
+
+use super::{AsyncFatFileSystem, AsyncStorageDevice};
+use crate::directory::attribute::Attributes;
+use crate::directory::cluster::Cluster;
+use crate::directory::dir_entry::DirectoryEntry;
+use crate::directory::table::FatValue;
+use crate::error::FsError;
+use crate::lock::OpenMode;
+use alloc::vec::Vec;
+
+/// An open file with its own read/write position.
+pub struct AsyncFile<'a, D: AsyncStorageDevice> {
+    fs: &'a AsyncFatFileSystem<D>,
+    entry: DirectoryEntry,
+    entry_offset: u64, // Slot of the entry, relative to the partition start.
+    position: u32,
+    mode: OpenMode, // Registered with the volume's open files until dropped.
+}
+
+impl<'a, D: AsyncStorageDevice> AsyncFile<'a, D> {
+    // Register the handle, failing with `Busy` if the mode conflicts.
+    pub(crate) fn open(
+        fs: &'a AsyncFatFileSystem<D>,
+        entry: DirectoryEntry,
+        entry_offset: u64,
+        mode: OpenMode,
+    ) -> Result<Self, FsError> {
+        fs.open_files.acquire(entry_offset, mode)?;
+        Ok(Self {
+            fs,
+            entry,
+            entry_offset,
+            position: 0,
+            mode,
+        })
+    }
+
+    /// The mode the file was opened in.
+    pub fn mode(&self) -> OpenMode {
+        self.mode
+    }
+
+    /// The directory entry as last written.
+    pub fn entry(&self) -> &DirectoryEntry {
+        &self.entry
+    }
+
+    /// Size of the file in bytes.
+    pub fn len(&self) -> u32 {
+        self.entry.file_size
+    }
+
+    /// Check if the file holds no data.
+    pub fn is_empty(&self) -> bool {
+        self.entry.file_size == 0
+    }
+
+    /// Current read/write position.
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// Move the read/write position. Writing past the end fills the gap with zeros.
+    pub fn seek(&mut self, position: u32) {
+        self.position = position;
+    }
+
+    /// Read from the current position, returning the number of bytes read (0 at the end).
+    pub async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let available = self.entry.file_size.saturating_sub(self.position) as usize;
+        let len = buffer.len().min(available);
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let cluster_size = self.fs.cluster_size() as usize;
+        let first = self.position as usize / cluster_size;
+        let clusters = self.fs.chain(self.entry.start_cluster).await?;
+        let mut done = 0;
+        for &cluster in clusters.iter().skip(first) {
+            if done == len {
+                break;
+            }
+            let within = (self.position as usize + done) % cluster_size;
+            let count = (cluster_size - within).min(len - done);
+            let offset = self.fs.cluster_offset(cluster) + within as u64;
+            self.fs.read_at(offset, &mut buffer[done..done + count]).await?;
+            done += count;
+        }
+        if done < len {
+            return Err(FsError::Io); // The chain is shorter than the file size.
+        }
+
+        self.position += len as u32;
+        Ok(len)
+    }
+
+    /// Write at the current position, growing the file as needed.
+    /// Fails with `AccessDenied` if the file is read-only or opened for reading.
+    pub async fn write(&mut self, data: &[u8]) -> Result<usize, FsError> {
+        if self.mode == OpenMode::Read || self.entry.attributes.is_read_only() {
+            return Err(FsError::AccessDenied);
+        }
+        let size = self.entry.file_size;
+        let end = (self.position as u64 + data.len() as u64)
+            .try_into()
+            .map_err(|_| FsError::InvalidArgument)?;
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        // 1. Allocate and link the clusters the new end needs.
+        let clusters = self.reserve(end).await?;
+        self.fs.barrier().await?;
+
+        // 2. Write the data, zero-filling any gap after the old end.
+        self.zero_range(&clusters, size, self.position).await?;
+        self.write_range(&clusters, self.position, data).await?;
+        self.fs.barrier().await?;
+
+        // 3. Publish the new size in the directory entry.
+        self.entry.file_size = self.entry.file_size.max(end);
+        self.entry.attributes |= Attributes::ARCHIVE;
+        self.fs.update_entry(self.entry_offset, &self.entry).await?;
+        self.fs.barrier().await?;
+
+        self.position = end;
+        Ok(data.len())
+    }
+
+    // Grow the chain to hold `len` bytes and return all of its clusters.
+    // New clusters are chained among themselves before a single FAT write
+    // attaches them, so a crash leaves either no extension or a whole one.
+    async fn reserve(&mut self, len: u32) -> Result<Vec<Cluster>, FsError> {
+        let mut clusters = match self.entry.start_cluster.0 {
+            0 => Vec::new(),
+            _ => self.fs.chain(self.entry.start_cluster).await?,
+        };
+        let needed = (len as u64).div_ceil(self.fs.cluster_size() as u64) as usize;
+        if clusters.len() >= needed {
+            return Ok(clusters);
+        }
+
+        let mut added: Vec<Cluster> = Vec::with_capacity(needed - clusters.len());
+        while clusters.len() + added.len() < needed {
+            match self.fs.allocate_cluster().await {
+                Ok(cluster) => added.push(cluster),
+                Err(err) => {
+                    for &cluster in &added {
+                        self.fs.free_cluster(cluster).await?;
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        for pair in added.windows(2) {
+            self.fs.set_fat_entry(pair[0], FatValue::Data(pair[1].0)).await?;
+        }
+
+        match clusters.last() {
+            Some(&last) => self.fs.set_fat_entry(last, FatValue::Data(added[0].0)).await?,
+            // An empty file only points at its chain once the entry is updated.
+            None => self.entry.start_cluster = added[0],
+        }
+        clusters.extend(added);
+        Ok(clusters)
+    }
+
+    // Write zeros from byte `start` up to `end`, one cluster at a time.
+    async fn zero_range(&self, clusters: &[Cluster], start: u32, end: u32) -> Result<(), FsError> {
+        if start >= end {
+            return Ok(());
+        }
+        let cluster_size = self.fs.cluster_size();
+        let zeros = vec![0u8; cluster_size as usize];
+        let mut position = start;
+        while position < end {
+            let count = (cluster_size - position % cluster_size).min(end - position);
+            self.write_range(clusters, position, &zeros[..count as usize]).await?;
+            position += count;
+        }
+        Ok(())
+    }
+
+    // Write `data` at byte `position` of the file laid out on `clusters`.
+    async fn write_range(&self, clusters: &[Cluster], position: u32, data: &[u8]) -> Result<(), FsError> {
+        let cluster_size = self.fs.cluster_size() as usize;
+        let mut done = 0;
+        while done < data.len() {
+            let position = position as usize + done;
+            let within = position % cluster_size;
+            let count = (cluster_size - within).min(data.len() - done);
+            let cluster = clusters[position / cluster_size];
+            self.fs.write_cluster_at(cluster, within as u32, &data[done..done + count]).await?;
+            done += count;
+        }
+        Ok(())
+    }
+}
+
+impl<D: AsyncStorageDevice> Drop for AsyncFile<'_, D> {
+    fn drop(&mut self) {
+        self.fs.open_files.release(self.entry_offset, self.mode);
+    }
+}
@@ -0,0 +1,153 @@
+//! Locks that tasks wait for without blocking their executor.
+//!
+//! The async counterparts of `crate::lock`: a waiting task is parked with
+//! its waker instead of spinning, and woken when a holder releases.
+
+use crate::directory::cluster::Cluster;
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use spin::Mutex;
+
+/// Mutual exclusion held across `.await` points.
+pub(crate) struct AsyncMutex {
+    locked: AtomicBool,
+    waiters: Mutex<Vec<Waker>>,
+}
+
+impl AsyncMutex {
+    pub(crate) const fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Wait until the lock is free and take it.
+    pub(crate) fn lock(&self) -> Acquire<'_> {
+        Acquire { mutex: self }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+}
+
+/// Future returned by `AsyncMutex::lock`.
+pub(crate) struct Acquire<'a> {
+    mutex: &'a AsyncMutex,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = AsyncMutexGuard<'a>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mutex = self.mutex;
+        if mutex.try_lock() {
+            return Poll::Ready(AsyncMutexGuard { mutex });
+        }
+        mutex.waiters.lock().push(cx.waker().clone());
+        // The holder may have released the lock before the waker was queued.
+        if mutex.try_lock() {
+            return Poll::Ready(AsyncMutexGuard { mutex });
+        }
+        Poll::Pending
+    }
+}
+
+/// Holds an `AsyncMutex` until dropped, then wakes the waiting tasks.
+pub(crate) struct AsyncMutexGuard<'a> {
+    mutex: &'a AsyncMutex,
+}
+
+impl Drop for AsyncMutexGuard<'_> {
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+        let waiters = core::mem::take(&mut *self.mutex.waiters.lock());
+        for waker in waiters {
+            waker.wake();
+        }
+    }
+}
+
+/// Directories being changed, by first cluster.
+pub(crate) struct AsyncDirectoryLocks {
+    locked: Mutex<BTreeSet<u32>>,
+    waiters: Mutex<Vec<Waker>>,
+}
+
+impl AsyncDirectoryLocks {
+    pub(crate) const fn new() -> Self {
+        Self {
+            locked: Mutex::new(BTreeSet::new()),
+            waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Wait until none of `dirs` is held, then lock all of them at once.
+    /// A task must not lock again while it holds a guard.
+    pub(crate) fn lock(&self, dirs: &[Cluster]) -> LockDirectories<'_> {
+        let mut clusters: Vec<u32> = dirs.iter().map(|dir| dir.0).collect();
+        clusters.sort_unstable();
+        clusters.dedup();
+        LockDirectories { locks: self, clusters }
+    }
+
+    fn try_lock(&self, clusters: &[u32]) -> bool {
+        let mut locked = self.locked.lock();
+        if clusters.iter().any(|cluster| locked.contains(cluster)) {
+            return false;
+        }
+        locked.extend(clusters.iter().copied());
+        true
+    }
+}
+
+/// Future returned by `AsyncDirectoryLocks::lock`.
+pub(crate) struct LockDirectories<'a> {
+    locks: &'a AsyncDirectoryLocks,
+    clusters: Vec<u32>,
+}
+
+impl<'a> Future for LockDirectories<'a> {
+    type Output = AsyncDirectoryGuard<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let locks = self.locks;
+        if !locks.try_lock(&self.clusters) {
+            locks.waiters.lock().push(cx.waker().clone());
+            // The holder may have released the directories before the waker was queued.
+            if !locks.try_lock(&self.clusters) {
+                return Poll::Pending;
+            }
+        }
+        let clusters = core::mem::take(&mut self.clusters);
+        Poll::Ready(AsyncDirectoryGuard { locks, clusters })
+    }
+}
+
+/// Directories locked by `AsyncDirectoryLocks::lock`, released on drop.
+pub(crate) struct AsyncDirectoryGuard<'a> {
+    locks: &'a AsyncDirectoryLocks,
+    clusters: Vec<u32>,
+}
+
+impl Drop for AsyncDirectoryGuard<'_> {
+    fn drop(&mut self) {
+        {
+            let mut locked = self.locks.locked.lock();
+            for cluster in &self.clusters {
+                locked.remove(cluster);
+            }
+        }
+        let waiters = core::mem::take(&mut *self.locks.waiters.lock());
+        for waker in waiters {
+            waker.wake();
+        }
+    }
+}
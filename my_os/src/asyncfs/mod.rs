@@ -0,0 +1,499 @@
+//! Asynchronous access to FAT volumes.
+//!
+//! `AsyncFatFileSystem` offers the cluster, directory and file operations
+//! of `FatFileSystem` on top of an `AsyncStorageDevice`, so tasks waiting
+//! for the disk yield to the scheduler instead of spinning. It shares the
+//! on-disk structures and the write ordering of the synchronous driver.
+//!
+//! The volume is flagged dirty while mounted, as by the synchronous
+//! driver. The journal is not supported: volumes that are dirty or have
+//! a journal are refused, and must be mounted synchronously instead.
+//! Tasks share a volume under the locking model of `crate::lock`:
+//! allocation is serialized, changes to a directory lock it, and open
+//! files enforce their sharing mode. Waiting tasks are parked rather
+//! than spinning.
+
+pub mod device;
+pub mod file;
+mod lock;
+
+pub use device::{AsyncStorageDevice, Blocking};
+pub use file::AsyncFile;
+
+use crate::boot_sector::{BootSector, FsInfo, FS_INFO_UNKNOWN};
+use crate::directory::attribute::Attributes;
+use crate::directory::cluster::Cluster;
+use crate::directory::dir_entry::{DirectoryEntry, DirectorySlot, ENTRY_SIZE};
+use crate::directory::table::{FatValue, FAT32_MASK};
+use crate::error::FsError;
+use crate::filesystem::{check_name, dirty_flags, Layout};
+use crate::journal::JOURNAL_FILE;
+use crate::volume::FatType;
+use alloc::vec::Vec;
+use crate::lock::{OpenFiles, OpenMode};
+use lock::{AsyncDirectoryLocks, AsyncMutex};
+use spin::Mutex;
+
+/// A FAT12, FAT16 or FAT32 volume on an asynchronous device.
+pub struct AsyncFatFileSystem<D: AsyncStorageDevice> {
+    device: D,
+    partition_start: u64,
+    layout: Layout,
+    fs_info: Mutex<Option<FsInfo>>, // Free cluster hints, written back by `sync`.
+    allocation: AsyncMutex,         // Held while a free cluster is found and claimed.
+    fat_update: AsyncMutex,         // Held across read-modify-writes of FAT entries.
+    pub(crate) open_files: OpenFiles,
+    directories: AsyncDirectoryLocks,
+}
+
+impl<D: AsyncStorageDevice> AsyncFatFileSystem<D> {
+    /// Mount a volume starting at offset zero of `device`.
+    pub async fn mount(device: D) -> Result<Self, FsError> {
+        Self::mount_at(device, 0).await
+    }
+
+    /// Mount a volume whose boot sector is at `partition_start`.
+    ///
+    /// Fails with `DirtyVolume` if the volume was not cleanly unmounted and
+    /// with `ReadOnly` if it has a journal, which only the synchronous
+    /// driver keeps.
+    pub async fn mount_at(device: D, partition_start: u64) -> Result<Self, FsError> {
+        let mut sector = [0u8; 512];
+        device.read(partition_start, &mut sector).await?;
+        let layout = Layout::from_boot(&BootSector::parse(&sector)?)?;
+        let fs = Self {
+            device,
+            partition_start,
+            layout,
+            fs_info: Mutex::new(None),
+            allocation: AsyncMutex::new(),
+            fat_update: AsyncMutex::new(),
+            open_files: OpenFiles::new(),
+            directories: AsyncDirectoryLocks::new(),
+        };
+
+        if let Some((clean, no_error)) = dirty_flags(layout.fat_type) {
+            let flags = fs.raw_fat_entry(Cluster(1)).await?;
+            if flags & clean == 0 || flags & no_error == 0 {
+                return Err(FsError::DirtyVolume);
+            }
+        }
+        // A later synchronous mount would replay the journal over our writes.
+        match fs.find_entry(layout.root_cluster, JOURNAL_FILE).await {
+            Ok((entry, _)) if entry.is_file() && entry.start_cluster.0 != 0 => {
+                return Err(FsError::ReadOnly)
+            }
+            Ok(_) | Err(FsError::NotFound) => {}
+            Err(err) => return Err(err),
+        }
+
+        // A damaged FSInfo sector only loses the hints.
+        if let Some(offset) = layout.fs_info_offset {
+            fs.read_at(offset, &mut sector).await?;
+            *fs.fs_info.lock() = FsInfo::parse(&sector).ok();
+        }
+        fs.set_clean(false).await?;
+        Ok(fs)
+    }
+
+    pub fn cluster_size(&self) -> u32 {
+        self.layout.cluster_size
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.layout.fat_type
+    }
+
+    /// First cluster of the root directory, 0 if it is the fixed FAT12/16 region.
+    pub fn root_cluster(&self) -> Cluster {
+        self.layout.root_cluster
+    }
+
+    /// Write the FSInfo hints back and flush the device.
+    pub async fn sync(&self) -> Result<(), FsError> {
+        let fs_info = *self.fs_info.lock();
+        if let (Some(offset), Some(fs_info)) = (self.layout.fs_info_offset, fs_info) {
+            self.write_at(offset, &fs_info.to_bytes()).await?;
+        }
+        self.device.flush().await
+    }
+
+    /// Flush everything, mark the volume clean and release the device.
+    pub async fn unmount(self) -> Result<D, FsError> {
+        self.sync().await?;
+        self.set_clean(true).await?;
+        Ok(self.device)
+    }
+
+    // Set or clear the clean-shutdown bit of FAT entry 1, if the FAT type has one.
+    async fn set_clean(&self, clean: bool) -> Result<(), FsError> {
+        let Some((bit, _)) = dirty_flags(self.layout.fat_type) else {
+            return Ok(());
+        };
+        let _update = self.fat_update.lock().await;
+        let flags = self.raw_fat_entry(Cluster(1)).await?;
+        let flags = if clean { flags | bit } else { flags & !bit };
+        self.store_raw_fat_entry(Cluster(1), flags).await?;
+        self.device.flush().await
+    }
+
+    /// Flush barrier between dependent updates.
+    pub async fn barrier(&self) -> Result<(), FsError> {
+        self.device.flush().await
+    }
+
+    /// Read bytes at an offset relative to the partition start.
+    pub async fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<(), FsError> {
+        self.device.read(self.partition_start + offset, buffer).await
+    }
+
+    /// Write bytes at an offset relative to the partition start.
+    pub async fn write_at(&self, offset: u64, data: &[u8]) -> Result<(), FsError> {
+        self.device.write(self.partition_start + offset, data).await
+    }
+
+    /// Offset of a data cluster, relative to the partition start.
+    pub fn cluster_offset(&self, cluster: Cluster) -> u64 {
+        self.layout.data_start + cluster.to_offset(self.layout.cluster_size)
+    }
+
+    /// Check if a cluster number lies inside the data region.
+    pub fn is_data_cluster(&self, cluster: Cluster) -> bool {
+        cluster.0 >= 2 && cluster.0 < self.layout.cluster_count + 2
+    }
+
+    fn is_fixed_root(&self, dir: Cluster) -> bool {
+        self.layout.root_dir_size > 0 && dir.0 == 0
+    }
+
+    /// Read a whole cluster.
+    pub async fn read_cluster(&self, cluster: Cluster) -> Result<Vec<u8>, FsError> {
+        let mut buffer = vec![0; self.layout.cluster_size as usize];
+        self.read_at(self.cluster_offset(cluster), &mut buffer).await?;
+        Ok(buffer)
+    }
+
+    /// Write data at byte `within` of a cluster.
+    pub async fn write_cluster_at(
+        &self,
+        cluster: Cluster,
+        within: u32,
+        data: &[u8],
+    ) -> Result<(), FsError> {
+        self.write_at(self.cluster_offset(cluster) + within as u64, data).await
+    }
+
+    // Offset and width of a cluster's entry in the first FAT.
+    fn fat_entry_location(&self, cluster: Cluster) -> (u64, usize) {
+        let offset = FatValue::entry_offset(self.layout.fat_type, cluster);
+        (self.layout.fat_start + offset, FatValue::entry_width(self.layout.fat_type))
+    }
+
+    /// Read the FAT entry of a cluster.
+    pub async fn fat_entry(&self, cluster: Cluster) -> Result<FatValue, FsError> {
+        let raw = self.raw_fat_entry(cluster).await?;
+        Ok(FatValue::decode(raw & FAT32_MASK, self.layout.fat_type))
+    }
+
+    /// Set the FAT entry of a cluster in every FAT copy.
+    pub async fn set_fat_entry(&self, cluster: Cluster, value: FatValue) -> Result<(), FsError> {
+        let _update = self.fat_update.lock().await;
+        let mut raw = value.encode(self.layout.fat_type);
+        if self.layout.fat_type == FatType::Fat32 {
+            // The upper 4 bits are reserved and must be preserved.
+            raw |= self.raw_fat_entry(cluster).await? & !FAT32_MASK;
+        }
+        self.store_raw_fat_entry(cluster, raw).await
+    }
+
+    // Raw FAT entry of a cluster from the first FAT, reserved bits included.
+    async fn raw_fat_entry(&self, cluster: Cluster) -> Result<u32, FsError> {
+        let (offset, width) = self.fat_entry_location(cluster);
+        let mut buffer = [0u8; 4];
+        self.read_at(offset, &mut buffer[..width]).await?;
+        Ok(FatValue::unpack(self.layout.fat_type, cluster, &buffer[..width]))
+    }
+
+    // Write a raw entry into every FAT copy with `fat_update` held, keeping
+    // the FAT12 neighbour that shares its bytes.
+    async fn store_raw_fat_entry(&self, cluster: Cluster, raw: u32) -> Result<(), FsError> {
+        let (offset, width) = self.fat_entry_location(cluster);
+        let mut buffer = [0u8; 4];
+        let bytes = &mut buffer[..width];
+        self.read_at(offset, bytes).await?;
+        FatValue::pack(self.layout.fat_type, cluster, bytes, raw);
+        for copy in 0..self.layout.num_fats as u64 {
+            self.write_at(offset + copy * self.layout.fat_size, bytes).await?;
+        }
+        Ok(())
+    }
+
+    /// Follow the cluster chain starting at `start`.
+    pub async fn chain(&self, start: Cluster) -> Result<Vec<Cluster>, FsError> {
+        let mut clusters = Vec::new();
+        let mut current = Some(start).filter(|&cluster| self.is_data_cluster(cluster));
+        while let Some(cluster) = current {
+            // A chain longer than the volume loops.
+            if clusters.len() as u32 == self.layout.cluster_count {
+                break;
+            }
+            clusters.push(cluster);
+            current = match self.fat_entry(cluster).await? {
+                FatValue::Data(next) if self.is_data_cluster(Cluster(next)) => Some(Cluster(next)),
+                _ => None,
+            };
+        }
+        Ok(clusters)
+    }
+
+    /// Allocate a new cluster, marked as the end of a chain.
+    pub async fn allocate_cluster(&self) -> Result<Cluster, FsError> {
+        // Other tasks run at every await; none may claim the same cluster.
+        let _allocation = self.allocation.lock().await;
+        // Start at the FSInfo hint and wrap around.
+        let end = self.layout.cluster_count + 2;
+        let hint = match *self.fs_info.lock() {
+            Some(FsInfo { next_free, .. }) if (2..end).contains(&next_free) => next_free,
+            _ => 2,
+        };
+        for cluster_id in (hint..end).chain(2..hint) {
+            if self.fat_entry(Cluster(cluster_id)).await? == FatValue::Free {
+                self.set_fat_entry(Cluster(cluster_id), FatValue::EndOfChain).await?;
+                if let Some(info) = self.fs_info.lock().as_mut() {
+                    if info.free_count != FS_INFO_UNKNOWN {
+                        info.free_count = info.free_count.saturating_sub(1);
+                    }
+                    info.next_free = cluster_id + 1;
+                }
+                return Ok(Cluster(cluster_id));
+            }
+        }
+        Err(FsError::NoSpace)
+    }
+
+    /// Free a cluster.
+    pub async fn free_cluster(&self, cluster: Cluster) -> Result<(), FsError> {
+        self.set_fat_entry(cluster, FatValue::Free).await?;
+        if let Some(info) = self.fs_info.lock().as_mut() {
+            if info.free_count != FS_INFO_UNKNOWN {
+                info.free_count = (info.free_count + 1).min(self.layout.cluster_count);
+            }
+        }
+        Ok(())
+    }
+
+    /// Iterate over directory `dir`.
+    pub fn read_dir(&self, dir: Cluster) -> AsyncDirectory<'_, D> {
+        AsyncDirectory {
+            fs: self,
+            dir,
+            clusters: None,
+            next_cluster: 0,
+            buffer: Vec::new(),
+            base: 0,
+            index: 0,
+        }
+    }
+
+    /// Look up an entry by its 8.3 name in directory `dir`.
+    /// Returns the entry and the offset of its slot.
+    pub async fn find_entry(
+        &self,
+        dir: Cluster,
+        name: &str,
+    ) -> Result<(DirectoryEntry, u64), FsError> {
+        let mut entries = self.read_dir(dir);
+        while let Some(item) = entries.next_with_offset().await {
+            let (entry, offset) = item?;
+            if entry.file_name().eq_ignore_ascii_case(name) {
+                return Ok((entry, offset));
+            }
+        }
+        Err(FsError::NotFound)
+    }
+
+    /// Resolve a `/`-separated path from the root directory.
+    /// The root itself resolves to its "." entry.
+    pub async fn lookup(&self, path: &str) -> Result<DirectoryEntry, FsError> {
+        let mut entry = DirectoryEntry::dot(self.layout.root_cluster);
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if !entry.is_directory() {
+                return Err(FsError::NotFound);
+            }
+            (entry, _) = self.find_entry(self.directory_cluster(&entry), name).await?;
+        }
+        Ok(entry)
+    }
+
+    /// First cluster of a directory, mapping cluster 0 to the root.
+    pub fn directory_cluster(&self, entry: &DirectoryEntry) -> Cluster {
+        match entry.start_cluster {
+            Cluster(0) => self.layout.root_cluster,
+            cluster => cluster,
+        }
+    }
+
+    /// Rewrite the name, attributes, start cluster and size of the entry at `offset`,
+    /// keeping its timestamps.
+    pub async fn update_entry(&self, offset: u64, entry: &DirectoryEntry) -> Result<(), FsError> {
+        let mut bytes = [0u8; ENTRY_SIZE];
+        self.read_at(offset, &mut bytes).await?;
+        entry.write_into(&mut bytes);
+        self.write_at(offset, &bytes).await
+    }
+
+    /// Store `entry` in the first free slot of directory `dir`, growing it if full.
+    /// Returns the offset of the slot used.
+    pub async fn add_entry(&self, dir: Cluster, entry: &DirectoryEntry) -> Result<u64, FsError> {
+        let _locked = self.directories.lock(&[dir]).await;
+        self.add_entry_inner(dir, entry).await
+    }
+
+    async fn add_entry_inner(&self, dir: Cluster, entry: &DirectoryEntry) -> Result<u64, FsError> {
+        let mut entries = self.read_dir(dir);
+        while let Some(slot) = entries.next_slot().await {
+            let slot = slot?;
+            if slot.is_end() || slot.is_deleted() {
+                self.write_at(slot.offset, &entry.to_bytes()).await?;
+                return Ok(slot.offset);
+            }
+        }
+        if self.is_fixed_root(dir) {
+            return Err(FsError::NoSpace); // The fixed root directory cannot grow.
+        }
+
+        // Zero the new cluster before linking it so the directory never ends in garbage.
+        let last = *self.chain(dir).await?.last().ok_or(FsError::Io)?;
+        let cluster = self.allocate_cluster().await?;
+        let zeros = vec![0; self.layout.cluster_size as usize];
+        if let Err(err) = self.write_cluster_at(cluster, 0, &zeros).await {
+            self.free_cluster(cluster).await?;
+            return Err(err);
+        }
+        self.set_fat_entry(last, FatValue::Data(cluster.0)).await?;
+
+        let offset = self.cluster_offset(cluster);
+        self.write_at(offset, &entry.to_bytes()).await?;
+        Ok(offset)
+    }
+
+    /// Create an empty file `name` in directory `dir` and open it.
+    pub async fn create_file(&self, dir: Cluster, name: &str) -> Result<AsyncFile<'_, D>, FsError> {
+        check_name(name)?;
+        let _locked = self.directories.lock(&[dir]).await;
+        match self.find_entry(dir, name).await {
+            Ok(_) => return Err(FsError::AlreadyExists),
+            Err(FsError::NotFound) => {}
+            Err(err) => return Err(err),
+        }
+        // No clusters yet, so the entry alone makes the file exist.
+        let entry = DirectoryEntry::new(name, Cluster(0), 0, Attributes::ARCHIVE);
+        let offset = self.add_entry_inner(dir, &entry).await?;
+        self.barrier().await?;
+        AsyncFile::open(self, entry, offset, OpenMode::Write)
+    }
+
+    /// Open the file `name` in directory `dir` for writing.
+    pub async fn open_file(&self, dir: Cluster, name: &str) -> Result<AsyncFile<'_, D>, FsError> {
+        self.open_file_with(dir, name, OpenMode::Write).await
+    }
+
+    /// Open the file `name` in directory `dir` in the given mode.
+    ///
+    /// Any number of readers can share a file, but a writer needs it to
+    /// itself: a conflicting open fails with `Busy`.
+    pub async fn open_file_with(
+        &self,
+        dir: Cluster,
+        name: &str,
+        mode: OpenMode,
+    ) -> Result<AsyncFile<'_, D>, FsError> {
+        // Keeps the entry from being replaced before the handle registers.
+        let _locked = self.directories.lock(&[dir]).await;
+        let (entry, offset) = self.find_entry(dir, name).await?;
+        if entry.is_directory() {
+            return Err(FsError::IsDirectory);
+        }
+        AsyncFile::open(self, entry, offset, mode)
+    }
+}
+
+/// Cursor over a directory, reading one cluster at a time.
+pub struct AsyncDirectory<'a, D: AsyncStorageDevice> {
+    fs: &'a AsyncFatFileSystem<D>,
+    dir: Cluster,
+    clusters: Option<Vec<Cluster>>, // The directory's chain, read on first use.
+    next_cluster: usize,
+    buffer: Vec<u8>,
+    base: u64,    // Offset of the buffered cluster.
+    index: usize, // Next slot in the buffer.
+}
+
+impl<D: AsyncStorageDevice> AsyncDirectory<'_, D> {
+    /// The next slot, including free ones, or `None` past the last cluster.
+    pub async fn next_slot(&mut self) -> Option<Result<DirectorySlot, FsError>> {
+        if self.index * ENTRY_SIZE >= self.buffer.len() {
+            if let Err(err) = self.load_next().await? {
+                self.buffer.clear();
+                return Some(Err(err));
+            }
+            self.index = 0;
+        }
+        let start = self.index * ENTRY_SIZE;
+        self.index += 1;
+        Some(Ok(DirectorySlot {
+            offset: self.base + start as u64,
+            bytes: self.buffer[start..start + ENTRY_SIZE].try_into().unwrap(),
+        }))
+    }
+
+    // Buffer the next part of the directory, or return `None` at its end.
+    async fn load_next(&mut self) -> Option<Result<(), FsError>> {
+        let fs = self.fs;
+        if fs.is_fixed_root(self.dir) {
+            if self.next_cluster > 0 {
+                return None;
+            }
+            self.next_cluster = 1;
+            self.base = fs.layout.root_dir_start;
+            self.buffer.resize(fs.layout.root_dir_size as usize, 0);
+        } else {
+            if self.clusters.is_none() {
+                match fs.chain(self.dir).await {
+                    Ok(clusters) => self.clusters = Some(clusters),
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+            let cluster = *self.clusters.as_ref()?.get(self.next_cluster)?;
+            self.next_cluster += 1;
+            self.base = fs.cluster_offset(cluster);
+            self.buffer.resize(fs.layout.cluster_size as usize, 0);
+        }
+        Some(fs.read_at(self.base, &mut self.buffer).await)
+    }
+
+    /// The next live entry and the offset of its slot.
+    pub async fn next_with_offset(&mut self) -> Option<Result<(DirectoryEntry, u64), FsError>> {
+        loop {
+            let slot = match self.next_slot().await? {
+                Ok(slot) => slot,
+                Err(err) => return Some(Err(err)),
+            };
+            if slot.is_end() {
+                return None;
+            }
+            if slot.is_deleted() || slot.is_long_name() {
+                continue;
+            }
+            let entry = slot.entry();
+            if !entry.is_volume_label() {
+                return Some(Ok((entry, slot.offset)));
+            }
+        }
+    }
+
+    /// The next live entry, skipping deleted slots, long names and the volume label.
+    pub async fn next(&mut self) -> Option<Result<DirectoryEntry, FsError>> {
+        Some(self.next_with_offset().await?.map(|(entry, _)| entry))
+    }
+}
@@ -0,0 +1,405 @@
+//! Host tool to inspect and modify FAT disk images.
+//!
+//! ```text
+//! fatimg [-p N] IMAGE COMMAND [ARGS]
+//! ```
+//!
+//! `-p N` selects partition N of a GPT or MBR disk instead of a bare volume.
+
+use my_os::defrag::defragment;
+use my_os::directory::attribute::Attributes;
+use my_os::directory::dir_entry::{DirectoryEntry, DirectoryIterator};
+use my_os::error::FsError;
+use my_os::file::File;
+use my_os::filesystem::{FatFileSystem, MountOptions, RemoveOptions, StorageDevice};
+use my_os::fsck::{check, FsckOptions};
+use my_os::lock::OpenMode;
+use my_os::mkfs::{format, FormatOptions};
+use my_os::partition::{Gpt, Mbr, Partition, PartitionDevice};
+use my_os::storage::FileStorage;
+use std::io::{self, Write};
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+usage: fatimg [-p N] IMAGE COMMAND [ARGS]
+
+commands:
+  info                    volume type, label, serial and usage
+  ls [-l] [PATH]          list a directory
+  cat PATH                write a file to stdout
+  cp-in HOST PATH         copy a host file into the image
+  cp-out PATH HOST        copy a file out of the image
+  mkdir PATH              create a directory
+  rm [-e] PATH            remove a file or an empty directory,
+                          erasing its data with -e
+  mv FROM TO              rename or move a file or directory
+  tree [PATH]             list a directory recursively
+  fsck [-r]               check the volume, repairing with -r
+  defrag                  make every file contiguous
+  wipe                    erase free clusters and deleted entries
+  mkfs [-s SIZE] [-L LABEL]
+                          format as FAT32, creating an image of SIZE bytes
+                          (suffixes K, M, G) when -s is given";
+
+type Device = PartitionDevice<FileStorage>;
+type Fs = FatFileSystem<Device>;
+type CliResult<T> = Result<T, String>;
+// Leading flags and their values, empty for flags without one.
+type Flags<'a> = Vec<(&'a str, &'a str)>;
+
+// Name `cp-in` writes under before replacing the target. A file already
+// holding it is never touched: the copy fails instead.
+const COPY_TEMP: &str = "FATIMG~.TMP";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(message) => {
+            eprintln!("fatimg: {message}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Prefix an error with the item it concerns.
+fn context<T, E: std::fmt::Display>(result: Result<T, E>, what: &str) -> CliResult<T> {
+    result.map_err(|err| format!("{what}: {err}"))
+}
+
+// Split leading `-x` flags (with a value when listed in `valued`) from operands.
+fn parse_flags<'a>(args: &'a [String], valued: &[&str]) -> CliResult<(Flags<'a>, Vec<&'a str>)> {
+    let mut flags = Vec::new();
+    let mut operands = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg.len() > 1 && arg.starts_with('-') {
+            let value = match valued.contains(&arg.as_str()) {
+                true => args.next().ok_or(format!("{arg} needs a value"))?,
+                false => "",
+            };
+            flags.push((arg.as_str(), value));
+        } else {
+            operands.push(arg.as_str());
+        }
+    }
+    Ok((flags, operands))
+}
+
+fn expect_args<const N: usize>(operands: &[&str], command: &str) -> CliResult<[String; N]> {
+    let owned: Vec<String> = operands.iter().map(|s| s.to_string()).collect();
+    owned.try_into().map_err(|_| format!("{command}: expected {N} argument(s)\n{USAGE}"))
+}
+
+fn run(args: &[String]) -> CliResult<bool> {
+    let (partition, args) = match args {
+        [flag, number, rest @ ..] if flag == "-p" => {
+            let number = number.parse::<u32>().map_err(|_| format!("bad partition number {number}"))?;
+            (Some(number), rest)
+        }
+        _ => (None, args),
+    };
+    let [image, command, rest @ ..] = args else {
+        return Err(USAGE.to_string());
+    };
+    let (flags, operands) = parse_flags(rest, &["-s", "-L"])?;
+    let has_flag = |name: &str| flags.iter().any(|&(flag, _)| flag == name);
+
+    let read_only = matches!(command.as_str(), "info" | "ls" | "cat" | "cp-out" | "tree")
+        || (command == "fsck" && !has_flag("-r"));
+    match command.as_str() {
+        "mkfs" => return mkfs(image, partition, &flags).map(|_| true),
+        "info" | "ls" | "cat" | "cp-out" | "cp-in" | "mkdir" | "rm" | "mv" | "tree" | "fsck"
+        | "defrag" | "wipe" => {}
+        _ => return Err(format!("unknown command {command}\n{USAGE}")),
+    }
+    let fs = mount(image, partition, read_only)?;
+
+    let result = execute(&fs, command, &flags, &operands);
+    // Unmount even after a failed command so the volume is left clean.
+    if !read_only {
+        context(fs.unmount(), image)?;
+    }
+    result
+}
+
+fn execute(fs: &Fs, command: &str, flags: &[(&str, &str)], operands: &[&str]) -> CliResult<bool> {
+    let has_flag = |name: &str| flags.iter().any(|&(flag, _)| flag == name);
+    let path_or_root = |index: usize| operands.get(index).copied().unwrap_or("/");
+    let ok = match command {
+        "info" => info(fs)?,
+        "ls" => ls(fs, path_or_root(0), has_flag("-l"))?,
+        "cat" => {
+            let [path] = expect_args(operands, "cat")?;
+            let mut file = open_in_image(fs, &path)?;
+            let mut stdout = io::stdout().lock();
+            copy_out(&mut file, &path, &mut stdout)?;
+            true
+        }
+        "cp-out" => {
+            let [path, host] = expect_args(operands, "cp-out")?;
+            // Only create the host file once the image file is known to exist.
+            let mut file = open_in_image(fs, &path)?;
+            let mut out = context(std::fs::File::create(&host), &host)?;
+            copy_out(&mut file, &path, &mut out)?;
+            true
+        }
+        "cp-in" => {
+            let [host, path] = expect_args(operands, "cp-in")?;
+            copy_in(fs, &host, &path)?;
+            true
+        }
+        "mkdir" => {
+            let [path] = expect_args(operands, "mkdir")?;
+            let (parent, name) = context(fs.lookup_parent(&path), &path)?;
+            context(fs.create_directory(parent, name), &path)?;
+            true
+        }
+        "rm" => {
+            let [path] = expect_args(operands, "rm")?;
+            let (parent, name) = context(fs.lookup_parent(&path), &path)?;
+            let options = RemoveOptions { erase: has_flag("-e") };
+            context(fs.remove_with(parent, name, options), &path)?;
+            true
+        }
+        "mv" => {
+            let [from, to] = expect_args(operands, "mv")?;
+            rename(fs, &from, &to)?;
+            true
+        }
+        "tree" => {
+            let path = path_or_root(0);
+            tree(fs, path)?;
+            true
+        }
+        "fsck" => {
+            let options = FsckOptions { repair: has_flag("-r"), ..Default::default() };
+            let report = context(check(fs, &options), "fsck")?;
+            for problem in &report.problems {
+                println!("{problem:?}");
+            }
+            println!("{} files, {} directories", report.files, report.directories);
+            if report.repaired {
+                println!("repaired");
+            }
+            report.is_clean() || report.repaired
+        }
+        "defrag" => {
+            let report = context(
+                defragment(fs, |progress| {
+                    eprint!("\r{}/{} files", progress.done, progress.total);
+                    true
+                }),
+                "defrag",
+            )?;
+            eprintln!("\r{0}/{0} files", report.files);
+            for path in &report.skipped {
+                println!("{path}: no free run large enough");
+            }
+            println!("{} files moved, {} clusters", report.moved, report.clusters_moved);
+            true
+        }
+        "wipe" => {
+            let report = context(fs.wipe_free_space(), "wipe")?;
+            let how = if report.discarded { "discarded" } else { "zeroed" };
+            println!("{} free clusters {how}, {} deleted entries cleared", report.clusters, report.slots);
+            true
+        }
+        _ => unreachable!("commands are checked before mounting"),
+    };
+    Ok(ok)
+}
+
+// Open the image and select the partition to work on.
+fn open_device(image: &str, partition: Option<u32>, read_only: bool) -> CliResult<Device> {
+    let storage = match read_only {
+        true => FileStorage::open_read_only(image),
+        false => FileStorage::open(image),
+    };
+    let storage = context(storage, image)?;
+    let range = match partition {
+        None => {
+            let length = storage.size().ok_or(format!("{image}: unknown size"))?;
+            Partition { start: 0, length }
+        }
+        // A protective MBR means the disk is partitioned with GPT.
+        Some(number) => match Gpt::read(&storage) {
+            Ok(gpt) => context(gpt.get(number), image)?.partition,
+            Err(_) => {
+                let mbr = context(Mbr::read(&storage), image)?;
+                let number = u8::try_from(number).map_err(|_| format!("{image}: {}", FsError::NotFound))?;
+                context(mbr.get(number), image)?.partition
+            }
+        },
+    };
+    context(PartitionDevice::new(storage, range), image)
+}
+
+fn mount(image: &str, partition: Option<u32>, read_only: bool) -> CliResult<Fs> {
+    let device = open_device(image, partition, read_only)?;
+    let options = MountOptions { read_only, ..Default::default() };
+    context(FatFileSystem::mount_with(device, 0, options), image)
+}
+
+fn mkfs(image: &str, partition: Option<u32>, flags: &[(&str, &str)]) -> CliResult<()> {
+    let mut options = FormatOptions::default();
+    for &(flag, value) in flags {
+        match flag {
+            "-s" if partition.is_none() => {
+                let size = parse_size(value).ok_or(format!("bad size {value}"))?;
+                context(FileStorage::create(image, size), image)?;
+            }
+            "-L" => options = options.with_label(value),
+            _ => return Err(format!("mkfs: unexpected {flag}")),
+        }
+    }
+    let device = open_device(image, partition, false)?;
+    let size = device.partition().length;
+    let boot = context(format(&device, size, &options), image)?;
+    println!(
+        "formatted {} clusters of {} bytes",
+        boot.cluster_count(),
+        boot.cluster_size()
+    );
+    Ok(())
+}
+
+// Parse a byte count with an optional K, M or G suffix.
+fn parse_size(text: &str) -> Option<u64> {
+    let (digits, unit) = match text.char_indices().last()? {
+        (at, 'K' | 'k') => (&text[..at], 1 << 10),
+        (at, 'M' | 'm') => (&text[..at], 1 << 20),
+        (at, 'G' | 'g') => (&text[..at], 1 << 30),
+        _ => (text, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+fn info(fs: &Fs) -> CliResult<bool> {
+    let stats = context(fs.stats(), "info")?;
+    println!("type:          {:?}", stats.fat_type);
+    println!("oem name:      {}", stats.oem_name);
+    println!("label:         {}", stats.label.as_deref().unwrap_or("(none)"));
+    println!("serial:        {:04X}-{:04X}", stats.volume_id >> 16, stats.volume_id & 0xFFFF);
+    println!("cluster size:  {}", stats.cluster_size);
+    println!("clusters:      {} ({} free)", stats.total_clusters, stats.free_clusters);
+    println!("size:          {} bytes", stats.total_bytes());
+    println!("used:          {} bytes", stats.used_bytes());
+    println!("free:          {} bytes", stats.free_bytes());
+    println!("clean:         {}", !(fs.was_dirty || fs.had_errors));
+    println!("journal:       {}", fs.has_journal());
+    Ok(true)
+}
+
+// Attribute letters as `ls -l` shows them.
+fn attribute_letters(attributes: Attributes) -> String {
+    [
+        (Attributes::DIRECTORY, 'd'),
+        (Attributes::READ_ONLY, 'r'),
+        (Attributes::HIDDEN, 'h'),
+        (Attributes::SYSTEM, 's'),
+        (Attributes::ARCHIVE, 'a'),
+    ]
+    .iter()
+    .map(|&(flag, letter)| if attributes.contains(flag) { letter } else { '-' })
+    .collect()
+}
+
+fn ls(fs: &Fs, path: &str, long: bool) -> CliResult<bool> {
+    let entry = context(fs.lookup(path), path)?;
+    let entries = match entry.is_directory() {
+        true => context(DirectoryIterator::new(fs, fs.directory_cluster(&entry)).collect(), path)?,
+        false => vec![entry],
+    };
+    for entry in entries {
+        if long {
+            println!(
+                "{} {:>10} {:>8} {}",
+                attribute_letters(entry.attributes),
+                entry.file_size,
+                entry.start_cluster.0,
+                entry.file_name()
+            );
+        } else {
+            println!("{}", entry.file_name());
+        }
+    }
+    Ok(true)
+}
+
+// Draw the tree below `path`. The walk visits it depth-first and never
+// enters a directory twice, so a looping volume still ends.
+fn tree(fs: &Fs, path: &str) -> CliResult<()> {
+    let walk = context(fs.walk(path), path)?;
+    let items: Vec<(String, DirectoryEntry)> = context(walk.collect(), path)?;
+    println!("{path}");
+    let depths: Vec<usize> = items.iter().map(|(item, _)| item.matches('/').count()).collect();
+    let top = depths.iter().copied().min().unwrap_or(0);
+    let mut open = Vec::new(); // Per level above the entry: more siblings follow.
+    for (i, (_, entry)) in items.iter().enumerate() {
+        let depth = depths[i];
+        let last = depths[i + 1..].iter().find(|&&next| next <= depth).is_none_or(|&next| next < depth);
+        open.truncate(depth - top);
+        let indent: String = open.iter().map(|&more| if more { "│   " } else { "    " }).collect();
+        let suffix = if entry.is_directory() { "/" } else { "" };
+        println!("{indent}{}{}{suffix}", if last { "└── " } else { "├── " }, entry.file_name());
+        open.push(!last);
+    }
+    Ok(())
+}
+
+fn open_in_image<'a>(fs: &'a Fs, path: &str) -> CliResult<File<'a, Device>> {
+    let (parent, name) = context(fs.lookup_parent(path), path)?;
+    context(fs.open_file_with(parent, name, OpenMode::Read), path)
+}
+
+fn copy_out(file: &mut File<'_, Device>, path: &str, out: &mut dyn Write) -> CliResult<()> {
+    context(io::copy(file, out), path)?;
+    context(out.flush(), path)
+}
+
+// Copy a host file in, replacing an existing file of the same name.
+// The data goes to a temporary file first, so a failed copy leaves the
+// existing file as it was.
+fn copy_in(fs: &Fs, host: &str, path: &str) -> CliResult<()> {
+    let data = context(std::fs::read(host), host)?;
+    let (parent, name) = context(fs.lookup_parent(path), path)?;
+    if name.eq_ignore_ascii_case(COPY_TEMP) {
+        return Err(format!("{path}: name reserved for copying"));
+    }
+    let replaces = match fs.find_entry(parent, name) {
+        Ok((entry, _)) if entry.is_directory() => return Err(format!("{path}: {}", FsError::IsDirectory)),
+        Ok(_) => true,
+        Err(FsError::NotFound) => false,
+        Err(err) => return Err(format!("{path}: {err}")),
+    };
+    let mut file = match fs.create_file(parent, COPY_TEMP) {
+        Err(FsError::AlreadyExists) => {
+            return Err(format!("{path}: {COPY_TEMP} already exists in the target directory"))
+        }
+        file => context(file, path)?,
+    };
+
+    // A single write links the whole chain in one transaction.
+    let written = file.write(&data);
+    drop(file);
+    if let Err(err) = written {
+        let _ = fs.remove(parent, COPY_TEMP);
+        return Err(format!("{path}: {err}"));
+    }
+    if replaces {
+        context(fs.remove(parent, name), path)?;
+    }
+    context(fs.rename(parent, COPY_TEMP, parent, name), path)
+}
+
+// Rename `from` to `to`, or move it into `to` when that is a directory.
+fn rename(fs: &Fs, from: &str, to: &str) -> CliResult<()> {
+    let (dir, name) = context(fs.lookup_parent(from), from)?;
+    let (new_dir, new_name) = match fs.lookup(to) {
+        Ok(target) if target.is_directory() => (fs.directory_cluster(&target), name),
+        _ => context(fs.lookup_parent(to), to)?,
+    };
+    context(fs.rename(dir, name, new_dir, new_name), from)
+}
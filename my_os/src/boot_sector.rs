@@ -0,0 +1,245 @@
+//! FAT32 boot sector (BPB) and FSInfo sector layout.
+
+use crate::error::FsError;
+
+/// Boot sector signature stored at offset 510.
+pub const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+/// FSInfo signatures.
+const FS_INFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FS_INFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FS_INFO_TRAIL_SIGNATURE: u32 = 0xAA55_0000;
+
+/// "Unknown" value for the FSInfo free count and next free hints.
+pub const FS_INFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+fn le_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn le_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// BIOS Parameter Block with the FAT32 extended fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootSector {
+    pub oem_name: [u8; 8],
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub num_fats: u8,
+    pub root_entry_count: u16, // Always 0 on FAT32.
+    pub total_sectors_16: u16,
+    pub media: u8,
+    pub fat_size_16: u16, // Always 0 on FAT32.
+    pub sectors_per_track: u16,
+    pub num_heads: u16,
+    pub hidden_sectors: u32,
+    pub total_sectors_32: u32,
+    pub fat_size_32: u32,
+    pub ext_flags: u16,
+    pub fs_version: u16,
+    pub root_cluster: u32,
+    pub fs_info_sector: u16,
+    pub backup_boot_sector: u16,
+    pub drive_number: u8,
+    pub boot_signature: u8, // 0x29 when the three fields below are valid.
+    pub volume_id: u32,
+    pub volume_label: [u8; 11],
+    pub fs_type: [u8; 8],
+}
+
+impl BootSector {
+    /// Parse and sanity-check a boot sector.
+    pub fn parse(sector: &[u8; 512]) -> Result<Self, FsError> {
+        if sector[510..512] != BOOT_SIGNATURE {
+            return Err(FsError::InvalidBootSector);
+        }
+
+        let mut boot = Self {
+            oem_name: sector[3..11].try_into().unwrap(),
+            bytes_per_sector: le_u16(sector, 11),
+            sectors_per_cluster: sector[13],
+            reserved_sectors: le_u16(sector, 14),
+            num_fats: sector[16],
+            root_entry_count: le_u16(sector, 17),
+            total_sectors_16: le_u16(sector, 19),
+            media: sector[21],
+            fat_size_16: le_u16(sector, 22),
+            sectors_per_track: le_u16(sector, 24),
+            num_heads: le_u16(sector, 26),
+            hidden_sectors: le_u32(sector, 28),
+            total_sectors_32: le_u32(sector, 32),
+            fat_size_32: 0,
+            ext_flags: 0,
+            fs_version: 0,
+            root_cluster: 0,
+            fs_info_sector: 0,
+            backup_boot_sector: 0,
+            drive_number: 0,
+            boot_signature: 0,
+            volume_id: 0,
+            volume_label: [b' '; 11],
+            fs_type: [b' '; 8],
+        };
+
+        // The FAT32 extension is only present when the 16-bit FAT size is zero.
+        if boot.fat_size_16 == 0 {
+            boot.fat_size_32 = le_u32(sector, 36);
+            boot.ext_flags = le_u16(sector, 40);
+            boot.fs_version = le_u16(sector, 42);
+            boot.root_cluster = le_u32(sector, 44);
+            boot.fs_info_sector = le_u16(sector, 48);
+            boot.backup_boot_sector = le_u16(sector, 50);
+            boot.drive_number = sector[64];
+            boot.boot_signature = sector[66];
+            boot.volume_id = le_u32(sector, 67);
+            boot.volume_label = sector[71..82].try_into().unwrap();
+            boot.fs_type = sector[82..90].try_into().unwrap();
+        } else {
+            // FAT12/16 extended BPB.
+            boot.drive_number = sector[36];
+            boot.boot_signature = sector[38];
+            boot.volume_id = le_u32(sector, 39);
+            boot.volume_label = sector[43..54].try_into().unwrap();
+            boot.fs_type = sector[54..62].try_into().unwrap();
+        }
+
+        let valid = boot.bytes_per_sector.is_power_of_two()
+            && (512..=4096).contains(&boot.bytes_per_sector)
+            && boot.sectors_per_cluster.is_power_of_two()
+            && boot.reserved_sectors > 0
+            && boot.num_fats > 0
+            && boot.fat_size() > 0
+            && boot.total_sectors() > boot.first_data_sector();
+        if !valid {
+            return Err(FsError::InvalidBootSector);
+        }
+        Ok(boot)
+    }
+
+    /// Serialize the boot sector, including the jump instruction and signature.
+    pub fn to_bytes(&self) -> [u8; 512] {
+        let mut sector = [0u8; 512];
+        sector[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]); // jmp short 0x5A; nop
+        sector[3..11].copy_from_slice(&self.oem_name);
+        sector[11..13].copy_from_slice(&self.bytes_per_sector.to_le_bytes());
+        sector[13] = self.sectors_per_cluster;
+        sector[14..16].copy_from_slice(&self.reserved_sectors.to_le_bytes());
+        sector[16] = self.num_fats;
+        sector[17..19].copy_from_slice(&self.root_entry_count.to_le_bytes());
+        sector[19..21].copy_from_slice(&self.total_sectors_16.to_le_bytes());
+        sector[21] = self.media;
+        sector[22..24].copy_from_slice(&self.fat_size_16.to_le_bytes());
+        sector[24..26].copy_from_slice(&self.sectors_per_track.to_le_bytes());
+        sector[26..28].copy_from_slice(&self.num_heads.to_le_bytes());
+        sector[28..32].copy_from_slice(&self.hidden_sectors.to_le_bytes());
+        sector[32..36].copy_from_slice(&self.total_sectors_32.to_le_bytes());
+        if self.fat_size_16 == 0 {
+            sector[36..40].copy_from_slice(&self.fat_size_32.to_le_bytes());
+            sector[40..42].copy_from_slice(&self.ext_flags.to_le_bytes());
+            sector[42..44].copy_from_slice(&self.fs_version.to_le_bytes());
+            sector[44..48].copy_from_slice(&self.root_cluster.to_le_bytes());
+            sector[48..50].copy_from_slice(&self.fs_info_sector.to_le_bytes());
+            sector[50..52].copy_from_slice(&self.backup_boot_sector.to_le_bytes());
+        }
+        let ext = self.label_offset() - 7; // Drive number of the extended BPB.
+        sector[ext] = self.drive_number;
+        sector[ext + 2] = self.boot_signature;
+        sector[ext + 3..ext + 7].copy_from_slice(&self.volume_id.to_le_bytes());
+        sector[ext + 7..ext + 18].copy_from_slice(&self.volume_label);
+        sector[ext + 18..ext + 26].copy_from_slice(&self.fs_type);
+        sector[510..512].copy_from_slice(&BOOT_SIGNATURE);
+        sector
+    }
+
+    /// Offset of the volume label within the sector.
+    pub fn label_offset(&self) -> usize {
+        if self.fat_size_16 == 0 {
+            71
+        } else {
+            43
+        }
+    }
+
+    /// Sectors per FAT copy.
+    pub fn fat_size(&self) -> u32 {
+        if self.fat_size_16 != 0 {
+            self.fat_size_16 as u32
+        } else {
+            self.fat_size_32
+        }
+    }
+
+    /// Total sectors in the volume.
+    pub fn total_sectors(&self) -> u32 {
+        if self.total_sectors_16 != 0 {
+            self.total_sectors_16 as u32
+        } else {
+            self.total_sectors_32
+        }
+    }
+
+    /// Cluster size in bytes.
+    pub fn cluster_size(&self) -> u32 {
+        self.bytes_per_sector as u32 * self.sectors_per_cluster as u32
+    }
+
+    /// Sectors used by a fixed root directory (FAT12/16 only).
+    pub fn root_dir_sectors(&self) -> u32 {
+        let bytes = self.root_entry_count as u32 * 32;
+        bytes.div_ceil(self.bytes_per_sector as u32)
+    }
+
+    /// First sector of the data region (cluster 2).
+    pub fn first_data_sector(&self) -> u32 {
+        self.reserved_sectors as u32
+            + self.num_fats as u32 * self.fat_size()
+            + self.root_dir_sectors()
+    }
+
+    /// Number of data clusters.
+    pub fn cluster_count(&self) -> u32 {
+        (self.total_sectors() - self.first_data_sector()) / self.sectors_per_cluster as u32
+    }
+}
+
+/// FAT32 FSInfo sector: free cluster hints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsInfo {
+    pub free_count: u32, // Last known free cluster count, or `FS_INFO_UNKNOWN`.
+    pub next_free: u32,  // Hint for the next free cluster, or `FS_INFO_UNKNOWN`.
+}
+
+impl FsInfo {
+    /// Parse an FSInfo sector, checking its three signatures.
+    pub fn parse(sector: &[u8; 512]) -> Result<Self, FsError> {
+        if le_u32(sector, 0) != FS_INFO_LEAD_SIGNATURE
+            || le_u32(sector, 484) != FS_INFO_STRUCT_SIGNATURE
+            || le_u32(sector, 508) != FS_INFO_TRAIL_SIGNATURE
+        {
+            return Err(FsError::InvalidBootSector);
+        }
+        Ok(Self {
+            free_count: le_u32(sector, 488),
+            next_free: le_u32(sector, 492),
+        })
+    }
+
+    /// Serialize the FSInfo sector.
+    pub fn to_bytes(&self) -> [u8; 512] {
+        let mut sector = [0u8; 512];
+        sector[0..4].copy_from_slice(&FS_INFO_LEAD_SIGNATURE.to_le_bytes());
+        sector[484..488].copy_from_slice(&FS_INFO_STRUCT_SIGNATURE.to_le_bytes());
+        sector[488..492].copy_from_slice(&self.free_count.to_le_bytes());
+        sector[492..496].copy_from_slice(&self.next_free.to_le_bytes());
+        sector[508..512].copy_from_slice(&FS_INFO_TRAIL_SIGNATURE.to_le_bytes());
+        sector
+    }
+}
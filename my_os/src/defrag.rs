@@ -0,0 +1,141 @@
+//! Offline defragmenter.
+//!
+//! Each fragmented file is moved on its own, in an order that keeps the
+//! volume consistent if a crash interrupts it:
+//! 1. allocate a contiguous run and chain it, which is only a lost chain;
+//! 2. copy the data into it;
+//! 3. point the directory entry at the run, making the old chain lost;
+//! 4. free the old chain.
+//!
+//! Files already contiguous are left alone, so running the defragmenter
+//! again after an interruption resumes where it stopped.
+//!
+//! Only regular files move: directory clusters are referenced from their
+//! children's ".." entries too. Files open elsewhere are skipped.
+
+use crate::directory::cluster::Cluster;
+use crate::directory::table::FatValue;
+use crate::error::FsError;
+use crate::file::File;
+use crate::filesystem::{FatFileSystem, StorageDevice};
+use crate::journal::JOURNAL_FILE;
+use crate::lock::OpenMode;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Progress passed to the callback of `defragment` before each file.
+#[derive(Debug, Clone, Copy)]
+pub struct DefragProgress<'a> {
+    pub path: &'a str,
+    pub done: u32,  // Files handled so far.
+    pub total: u32, // Files on the volume.
+}
+
+/// Result of a defragmentation run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DefragReport {
+    pub files: u32,           // Files examined.
+    pub moved: u32,           // Fragmented files made contiguous.
+    pub clusters_moved: u32,  // Clusters copied to their new run.
+    pub skipped: Vec<String>, // Files left as is: open elsewhere, or no free run was long enough.
+    pub interrupted: bool,    // The callback stopped the run early.
+}
+
+/// Make every fragmented file contiguous.
+///
+/// `progress` is called before each file; returning `false` stops the run
+/// between two files, leaving the volume consistent.
+pub fn defragment<S: StorageDevice>(
+    fs: &FatFileSystem<S>,
+    mut progress: impl FnMut(DefragProgress<'_>) -> bool,
+) -> Result<DefragReport, FsError> {
+    if fs.is_read_only() {
+        return Err(FsError::ReadOnly);
+    }
+    // Collect the paths first: moving files rewrites the directories being walked.
+    let journal = format!("/{}", JOURNAL_FILE);
+    let mut paths = Vec::new();
+    for item in fs.walk("/")? {
+        let (path, entry) = item?;
+        // The journal is in use and its clusters are cached while mounted.
+        if entry.is_file() && path != journal {
+            paths.push(path);
+        }
+    }
+
+    let total = paths.len() as u32;
+    let mut report = DefragReport::default();
+    for (done, path) in paths.iter().enumerate() {
+        if !progress(DefragProgress { path, done: done as u32, total }) {
+            report.interrupted = true;
+            break;
+        }
+        report.files += 1;
+        let (dir, name) = fs.lookup_parent(path)?;
+        // Files open elsewhere are left where they are.
+        let file = match fs.open_file_with(dir, name, OpenMode::Write) {
+            Err(FsError::Busy) => {
+                report.skipped.push(path.clone());
+                continue;
+            }
+            file => file?,
+        };
+        if file.extents()?.len() <= 1 {
+            continue;
+        }
+        match fs.transaction(|| relocate(&file))? {
+            Some(clusters) => {
+                report.moved += 1;
+                report.clusters_moved += clusters;
+            }
+            None => report.skipped.push(path.clone()),
+        }
+    }
+    fs.sync()?;
+    Ok(report)
+}
+
+// Move a file to a contiguous run, returning the number of clusters moved,
+// or `None` if no free run is long enough.
+fn relocate<S: StorageDevice>(file: &File<'_, S>) -> Result<Option<u32>, FsError> {
+    let fs = file.fs();
+    let mut entry = *file.entry();
+    let old: Vec<Cluster> = fs.chain(entry.start_cluster).collect::<Result<_, _>>()?;
+    let count = old.len() as u32;
+
+    // 1. Take a run and chain it.
+    let run = match fs.allocate_clusters(count, None) {
+        Err(FsError::NoSpace) => return Ok(None),
+        run => run?,
+    };
+    if run.windows(2).any(|pair| pair[1].0 != pair[0].0 + 1) {
+        for &cluster in &run {
+            fs.free_cluster(cluster)?;
+        }
+        return Ok(None);
+    }
+    for pair in run.windows(2) {
+        FatValue::put(fs, pair[0], FatValue::Data(pair[1].0))?;
+    }
+    fs.barrier()?;
+
+    // 2. Copy the data.
+    for (&from, &to) in old.iter().zip(&run) {
+        let data = fs.read_cluster(from).ok_or(FsError::Io)?;
+        fs.write_cluster(to, &data)?;
+    }
+    fs.barrier()?;
+
+    // 3. Switch the entry over.
+    entry.start_cluster = run[0];
+    fs.update_entry(file.entry_offset(), &entry)?;
+    fs.barrier()?;
+
+    // 4. Release the old chain.
+    for cluster in old {
+        fs.free_cluster(cluster)?;
+    }
+    fs.barrier()?;
+    Ok(Some(count))
+}
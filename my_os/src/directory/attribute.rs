@@ -1,36 +1,76 @@
-/// File attribute flags for FAT32.
-#[derive(Debug, Clone, Copy)]
-pub struct Attributes(u8);
+use bitflags::bitflags;
+
+bitflags! {
+    /// File attribute flags for FAT32.
+    ///
+    /// Raw entries may carry the two reserved high bits; they are kept as read.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct Attributes: u8 {
+        const READ_ONLY = 0x01;
+        const HIDDEN = 0x02;
+        const SYSTEM = 0x04;
+        const VOLUME_ID = 0x08;
+        const DIRECTORY = 0x10;
+        const ARCHIVE = 0x20;
+        /// Combination marking a long file name entry.
+        const LONG_NAME = 0x0F;
+    }
+}
 
 impl Attributes {
-    pub const READ_ONLY: u8 = 0x01;
-    pub const HIDDEN: u8 = 0x02;
-    pub const SYSTEM: u8 = 0x04;
-    pub const DIRECTORY: u8 = 0x10;
-    pub const ARCHIVE: u8 = 0x20;
+    /// Flags a user may change; the others describe what the entry is.
+    pub const USER: Attributes = Attributes::READ_ONLY
+        .union(Attributes::HIDDEN)
+        .union(Attributes::SYSTEM)
+        .union(Attributes::ARCHIVE);
 
+    /// Wrap a raw attribute byte.
     pub fn new(value: u8) -> Self {
-        Self(value)
+        Self::from_bits_retain(value)
     }
 
     pub fn is_read_only(self) -> bool {
-        self.0 & Self::READ_ONLY != 0
+        self.contains(Self::READ_ONLY)
     }
 
     pub fn is_hidden(self) -> bool {
-        self.0 & Self::HIDDEN != 0
+        self.contains(Self::HIDDEN)
     }
 
     pub fn is_system(self) -> bool {
-        self.0 & Self::SYSTEM != 0
+        self.contains(Self::SYSTEM)
+    }
+
+    pub fn is_volume_id(self) -> bool {
+        self.contains(Self::VOLUME_ID)
     }
 
     pub fn is_directory(self) -> bool {
-        self.0 & Self::DIRECTORY != 0
+        self.contains(Self::DIRECTORY)
     }
 
     pub fn is_archive(self) -> bool {
-        self.0 & Self::ARCHIVE != 0
+        self.contains(Self::ARCHIVE)
+    }
+
+    /// Check if the byte marks a long file name entry rather than a short one.
+    pub fn is_long_name(self) -> bool {
+        self.bits() & 0x3F == Self::LONG_NAME.bits()
     }
-}
 
+    pub fn set_read_only(&mut self, value: bool) {
+        self.set(Self::READ_ONLY, value);
+    }
+
+    pub fn set_hidden(&mut self, value: bool) {
+        self.set(Self::HIDDEN, value);
+    }
+
+    pub fn set_system(&mut self, value: bool) {
+        self.set(Self::SYSTEM, value);
+    }
+
+    pub fn set_archive(&mut self, value: bool) {
+        self.set(Self::ARCHIVE, value);
+    }
+}
@@ -21,6 +21,11 @@ impl FatDateTime {
         }
     }
 
+    /// Packs the date into the 16-bit on-disk format (years since 1980).
+    pub fn to_fat_date(&self) -> u16 {
+        (self.year.saturating_sub(1980) << 9) | ((self.month as u16) << 5) | self.day as u16
+    }
+
     /// Converts the FAT date-time to a UNIX timestamp (simple approximation).
     pub fn to_unix_timestamp(&self) -> u64 {
         let days = (self.year as u64 - 1970) * 365 + (self.month as u64 * 30) + self.day as u64;
@@ -1,51 +1,121 @@
 //! High-level directory entry representation for FAT32.
 
+use crate::directory::attribute::Attributes;
 use crate::directory::cluster::Cluster;
-use alloc::string::{String, ToString};
+use crate::directory::name::ShortFileName;
+use crate::directory::offset_iter::ClusterChain;
+use crate::error::FsError;
+use crate::filesystem::{FatFileSystem, StorageDevice};
 use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Size of an on-disk directory entry.
+pub const ENTRY_SIZE: usize = 32;
+/// First name byte marking the end of a directory.
+pub const END_OF_DIRECTORY: u8 = 0x00;
+/// First name byte marking a deleted entry.
+pub const DELETED_ENTRY: u8 = 0xE5;
+/// Attribute combination used by long file name entries.
+pub const LONG_NAME: u8 = Attributes::LONG_NAME.bits();
 
 /// Represents metadata about a directory entry.
 #[derive(Debug, Clone, Copy)]
 pub struct DirectoryEntry {
     pub file_name: [u8; 11], // 8.3 format (8 chars name + 3 chars extension)
-    pub attributes: u8,      // File attributes (read-only, hidden, etc.)
+    pub attributes: Attributes, // File attributes (read-only, hidden, etc.)
     pub start_cluster: Cluster,
     pub file_size: u32,
 }
 
 impl DirectoryEntry {
     /// Create a new directory entry.
-    pub fn new(file_name: &str, start_cluster: Cluster, file_size: u32, attributes: u8) -> Self {
-        let mut name_bytes = [0u8; 11];
-        let name = file_name.as_bytes();
-
-        // Copy filename into the 8.3 format
-        let name_part = &name[0..name.len().min(8)];
-        name_bytes[..name_part.len()].copy_from_slice(name_part);
-
-        // Optional extension if present
-        if let Some(dot_pos) = file_name.find('.') {
-            let ext = &name[dot_pos + 1..];
-            let ext_part = &ext[0..ext.len().min(3)];
-            name_bytes[8..8 + ext_part.len()].copy_from_slice(ext_part);
-        }
+    pub fn new(
+        file_name: &str,
+        start_cluster: Cluster,
+        file_size: u32,
+        attributes: Attributes,
+    ) -> Self {
+        // Split "NAME.EXT" at the last dot; the extension is optional.
+        let (name, ext) = match file_name.rfind('.') {
+            Some(dot) if dot > 0 => (&file_name[..dot], &file_name[dot + 1..]),
+            _ => (file_name, ""),
+        };
 
         Self {
-            file_name: name_bytes,
+            file_name: *ShortFileName::new(name, ext).as_bytes(),
             attributes,
             start_cluster,
             file_size,
         }
     }
 
+    /// The "." entry of a directory starting at `cluster`.
+    pub fn dot(cluster: Cluster) -> Self {
+        Self {
+            file_name: *b".          ",
+            attributes: Attributes::DIRECTORY,
+            start_cluster: cluster,
+            file_size: 0,
+        }
+    }
+
+    /// The ".." entry pointing to `parent` (cluster 0 for the root directory).
+    pub fn dot_dot(parent: Cluster) -> Self {
+        Self {
+            file_name: *b"..         ",
+            attributes: Attributes::DIRECTORY,
+            start_cluster: parent,
+            file_size: 0,
+        }
+    }
+
+    /// Parse a 32-byte on-disk entry.
+    pub fn from_bytes(bytes: &[u8; ENTRY_SIZE]) -> Self {
+        let high = u16::from_le_bytes([bytes[20], bytes[21]]) as u32;
+        let low = u16::from_le_bytes([bytes[26], bytes[27]]) as u32;
+        Self {
+            file_name: bytes[0..11].try_into().unwrap(),
+            attributes: Attributes::new(bytes[11]),
+            start_cluster: Cluster((high << 16) | low),
+            file_size: u32::from_le_bytes([bytes[28], bytes[29], bytes[30], bytes[31]]),
+        }
+    }
+
+    /// Serialize the entry into its 32-byte on-disk form (timestamps left zero).
+    pub fn to_bytes(&self) -> [u8; ENTRY_SIZE] {
+        let mut bytes = [0u8; ENTRY_SIZE];
+        self.write_into(&mut bytes);
+        bytes
+    }
+
+    /// Overwrite the name, attributes, start cluster and size of a raw entry.
+    pub fn write_into(&self, bytes: &mut [u8; ENTRY_SIZE]) {
+        bytes[0..11].copy_from_slice(&self.file_name);
+        bytes[11] = self.attributes.bits();
+        bytes[20..22].copy_from_slice(&((self.start_cluster.0 >> 16) as u16).to_le_bytes());
+        bytes[26..28].copy_from_slice(&(self.start_cluster.0 as u16).to_le_bytes());
+        bytes[28..32].copy_from_slice(&self.file_size.to_le_bytes());
+    }
+
     /// Check if the entry is a directory.
     pub fn is_directory(&self) -> bool {
-        self.attributes & 0x10 != 0
+        self.attributes.is_directory()
     }
 
     /// Check if the entry is a file.
     pub fn is_file(&self) -> bool {
-        self.attributes & 0x10 == 0
+        !self.attributes.is_directory()
+    }
+
+    /// Check if the entry holds the volume label.
+    pub fn is_volume_label(&self) -> bool {
+        self.attributes.is_volume_id()
+    }
+
+    /// Check if the entry is "." or "..".
+    pub fn is_dot(&self) -> bool {
+        self.file_name[0] == b'.'
     }
 
     /// Convert the raw filename into a String.
@@ -65,3 +135,123 @@ impl DirectoryEntry {
     }
 }
 
+/// A raw 32-byte directory slot and its position on disk.
+#[derive(Debug, Clone, Copy)]
+pub struct DirectorySlot {
+    pub offset: u64, // Offset of the slot, relative to the partition start.
+    pub bytes: [u8; ENTRY_SIZE],
+}
+
+impl DirectorySlot {
+    /// Check if the slot marks the end of the directory.
+    pub fn is_end(&self) -> bool {
+        self.bytes[0] == END_OF_DIRECTORY
+    }
+
+    /// Check if the slot holds a deleted entry.
+    pub fn is_deleted(&self) -> bool {
+        self.bytes[0] == DELETED_ENTRY
+    }
+
+    /// Check if the slot is part of a long file name.
+    pub fn is_long_name(&self) -> bool {
+        Attributes::new(self.bytes[11]).is_long_name()
+    }
+
+    /// Interpret the slot as a short entry.
+    pub fn entry(&self) -> DirectoryEntry {
+        DirectoryEntry::from_bytes(&self.bytes)
+    }
+}
+
+/// Iterator over every slot of a directory, including free ones.
+pub struct DirectorySlots<'a, S: StorageDevice> {
+    fs: &'a FatFileSystem<S>,
+    chain: ClusterChain<'a, S>,
+    fixed_root: bool, // The fixed FAT12/16 root region is still to be read.
+    buffer: Vec<u8>,
+    base: u64,    // Offset of the buffered cluster.
+    index: usize, // Next slot in the buffer.
+}
+
+impl<'a, S: StorageDevice> DirectorySlots<'a, S> {
+    pub fn new(fs: &'a FatFileSystem<S>, cluster: Cluster) -> Self {
+        Self {
+            fs,
+            chain: fs.chain(cluster),
+            fixed_root: fs.is_fixed_root(cluster),
+            buffer: Vec::new(),
+            base: 0,
+            index: 0,
+        }
+    }
+}
+
+impl<S: StorageDevice> Iterator for DirectorySlots<'_, S> {
+    type Item = Result<DirectorySlot, FsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index * ENTRY_SIZE >= self.buffer.len() {
+            if core::mem::take(&mut self.fixed_root) {
+                self.base = self.fs.root_dir_start;
+                self.buffer.resize(self.fs.root_dir_size as usize, 0);
+            } else {
+                let cluster = match self.chain.next()? {
+                    Ok(cluster) => cluster,
+                    Err(err) => return Some(Err(err)),
+                };
+                self.base = self.fs.cluster_offset(cluster);
+                self.buffer.resize(self.fs.cluster_size as usize, 0);
+            }
+            if let Err(err) = self.fs.read_at(self.base, &mut self.buffer) {
+                self.buffer.clear();
+                return Some(Err(err));
+            }
+            self.index = 0;
+        }
+
+        let start = self.index * ENTRY_SIZE;
+        self.index += 1;
+        Some(Ok(DirectorySlot {
+            offset: self.base + start as u64,
+            bytes: self.buffer[start..start + ENTRY_SIZE].try_into().unwrap(),
+        }))
+    }
+}
+
+/// Iterator over the live entries of a directory. A read error is
+/// returned as the last item rather than ending the listing early.
+pub struct DirectoryIterator<'a, S: StorageDevice> {
+    slots: DirectorySlots<'a, S>,
+}
+
+impl<'a, S: StorageDevice> DirectoryIterator<'a, S> {
+    pub fn new(fs: &'a FatFileSystem<S>, cluster: Cluster) -> Self {
+        Self {
+            slots: DirectorySlots::new(fs, cluster),
+        }
+    }
+}
+
+impl<S: StorageDevice> Iterator for DirectoryIterator<'_, S> {
+    type Item = Result<DirectoryEntry, FsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let slot = match self.slots.next()? {
+                Ok(slot) => slot,
+                Err(err) => return Some(Err(err)),
+            };
+            if slot.is_end() {
+                return None;
+            }
+            if slot.is_deleted() || slot.is_long_name() {
+                continue;
+            }
+            let entry = slot.entry();
+            if !entry.is_volume_label() {
+                return Some(Ok(entry));
+            }
+        }
+    }
+}
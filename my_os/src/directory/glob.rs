@@ -0,0 +1,216 @@
+//! Wildcard matching of entry names and paths.
+//!
+//! Two syntaxes are supported:
+//! - DOS: `*` and `?` applied to the 8.3 fields, so `*` fills the rest of
+//!   the name or extension and `?` also matches padding. As in DOS, `*`
+//!   alone only matches names without an extension; `*.*` matches all.
+//! - Unix: `*`, `?`, character classes (`[abc]`, `[a-z]`, `[!a]`) on the
+//!   displayed name, and `**` matching any number of directories. A
+//!   component matches if either the long name or the 8.3 name does.
+//!
+//! FAT names are case-insensitive, so both syntaxes ignore ASCII case.
+
+use crate::directory::dir_entry::DirectoryEntry;
+use crate::directory::walk::{Walk, WalkOptions};
+use crate::error::FsError;
+use crate::filesystem::{FatFileSystem, StorageDevice};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Pattern syntax for `FatFileSystem::find_with`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GlobSyntax {
+    Dos,
+    #[default]
+    Unix,
+}
+
+// Expand one field of a DOS pattern to its fixed width.
+fn expand_field(pattern: &str, width: usize) -> Option<Vec<u8>> {
+    let mut field = Vec::with_capacity(width);
+    for byte in pattern.bytes() {
+        if byte == b'*' {
+            field.resize(width, b'?');
+            break;
+        }
+        field.push(byte.to_ascii_uppercase());
+    }
+    if field.len() > width {
+        return None;
+    }
+    field.resize(width, b' ');
+    Some(field)
+}
+
+/// Match an entry's 8.3 name against a DOS pattern such as `*.TXT` or `REPORT??.*`.
+/// Patterns that do not fit the 8.3 fields match nothing.
+pub fn dos_match(pattern: &str, entry: &DirectoryEntry) -> bool {
+    let (name, ext) = pattern.rsplit_once('.').unwrap_or((pattern, ""));
+    let (Some(name), Some(ext)) = (expand_field(name, 8), expand_field(ext, 3)) else {
+        return false;
+    };
+    name.iter()
+        .chain(&ext)
+        .zip(&entry.file_name)
+        .all(|(&wanted, &actual)| wanted == b'?' || wanted == actual.to_ascii_uppercase())
+}
+
+// Match `c` against the class starting at `pattern[start]` (a '[').
+// Returns whether it matched and the index after the class, or `None`
+// if the class is not closed (the '[' is then literal).
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(pattern.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let c = c.to_ascii_uppercase();
+    let mut matched = false;
+    let first = i;
+    // A ']' right after the opening bracket is a member, not the end.
+    while i < pattern.len() && (pattern[i] != ']' || i == first) {
+        let low = pattern[i];
+        let high = match (pattern.get(i + 1), pattern.get(i + 2)) {
+            (Some('-'), Some(&high)) if high != ']' => {
+                i += 2;
+                high
+            }
+            _ => low,
+        };
+        matched |= (low.to_ascii_uppercase()..=high.to_ascii_uppercase()).contains(&c);
+        i += 1;
+    }
+    (i < pattern.len()).then_some((matched != negated, i + 1))
+}
+
+/// Match a name against a Unix glob pattern (`*`, `?` and classes, no `/`).
+pub fn unix_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None; // Last `*` and the name index it resumes from.
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') => match match_class(&pattern, p, name[n]) {
+                Some((true, next)) => Some(next),
+                Some((false, _)) => None,
+                None => (name[n] == '[').then_some(p + 1),
+            },
+            Some(c) if c.eq_ignore_ascii_case(&name[n]) => Some(p + 1),
+            _ => None,
+        };
+        match (step, star) {
+            (Some(next), _) => {
+                p = next;
+                n += 1;
+            }
+            // Let the last `*` swallow one more character and retry.
+            (None, Some((star_p, star_n))) => {
+                p = star_p + 1;
+                n = star_n + 1;
+                star = Some((star_p, star_n + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Match path components, each an 8.3 and a long name, against pattern
+// components, `**` spanning any number.
+fn match_components(pattern: &[String], path: &[(&str, &str)]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skip| match_components(rest, &path[skip..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some(((short, long), path)) => {
+                (unix_match(first, short) || unix_match(first, long)) && match_components(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+fn has_wildcard(component: &str) -> bool {
+    component.contains(['*', '?', '['])
+}
+
+/// Iterator over the entries matching a pattern.
+pub struct Find<'a, S: StorageDevice> {
+    walk: Walk<'a, S>,
+    base: usize,          // Length of the literal directory prefix in yielded paths.
+    pattern: Vec<String>, // Components after the prefix.
+    syntax: GlobSyntax,
+}
+
+impl<S: StorageDevice> Iterator for Find<'_, S> {
+    type Item = Result<(String, DirectoryEntry), FsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (path, entry) = match self.walk.next()? {
+                Ok(item) => item,
+                Err(err) => return Some(Err(err)),
+            };
+            let matched = match self.syntax {
+                GlobSyntax::Dos => dos_match(&self.pattern[0], &entry),
+                GlobSyntax::Unix => {
+                    // Both paths share the literal prefix and their component count.
+                    let long = &self.walk.long_path()[self.base..];
+                    let relative: Vec<(&str, &str)> =
+                        path[self.base..].split('/').zip(long.split('/')).skip(1).collect();
+                    match_components(&self.pattern, &relative)
+                }
+            };
+            if matched {
+                return Some(Ok((path, entry)));
+            }
+        }
+    }
+}
+
+impl<S: StorageDevice> FatFileSystem<S> {
+    /// Find the entries matching a Unix glob such as `/LOGS/**/*.LOG`.
+    pub fn find(&self, pattern: &str) -> Result<Find<'_, S>, FsError> {
+        self.find_with(pattern, GlobSyntax::Unix)
+    }
+
+    /// Find the entries matching `pattern` in the given syntax.
+    /// DOS patterns only allow wildcards in the last component.
+    pub fn find_with(&self, pattern: &str, syntax: GlobSyntax) -> Result<Find<'_, S>, FsError> {
+        let components: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+        if components.is_empty() || pattern.ends_with('/') {
+            return Err(FsError::InvalidArgument);
+        }
+        // Start from the deepest directory named without wildcards.
+        let literal = components[..components.len() - 1]
+            .iter()
+            .take_while(|component| !has_wildcard(component))
+            .count();
+        let (prefix, rest) = components.split_at(literal);
+        if syntax == GlobSyntax::Dos && rest.len() > 1 {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let max_depth = match rest.contains(&"**") {
+            true => None,
+            false => Some(rest.len()),
+        };
+        let options = WalkOptions { max_depth, ..Default::default() };
+        let base: String = prefix.iter().flat_map(|c| ["/", c]).collect();
+        Ok(Find {
+            walk: self.walk_with(&base, options)?,
+            base: base.len(),
+            pattern: rest.iter().map(|c| c.to_string()).collect(),
+            syntax,
+        })
+    }
+}
@@ -6,3 +6,5 @@ pub mod name;
 pub mod datetime;
 pub mod offset_iter;
 
+pub mod walk;
+pub mod glob;
@@ -1,5 +1,40 @@
+use crate::directory::dir_entry::ENTRY_SIZE;
 use alloc::string::String;
 use alloc::format;
+use alloc::vec::Vec;
+
+/// Characters that may not appear in a short name.
+pub const ILLEGAL_NAME_CHARS: &[u8] = b"\"*+,./:;<=>?[\\]|";
+
+// Byte ranges of the UTF-16 characters in a long name slot.
+const LONG_NAME_CHARS: [(usize, usize); 3] = [(1, 11), (14, 26), (28, 32)];
+
+/// Checksum of a short name, stored in each of its long name slots.
+pub fn short_name_checksum(name: &[u8; 11]) -> u8 {
+    name.iter().fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+// Decode long name slots, given farthest from the entry first, returning
+// the name and the checksum they share.
+pub(crate) fn decode_long_name(slots: &[[u8; ENTRY_SIZE]]) -> Option<(String, u8)> {
+    let checksum = slots.first()?[13];
+    if slots.iter().any(|slot| slot[13] != checksum) {
+        return None;
+    }
+    // Name order runs from the slot nearest the entry outwards.
+    let units: Vec<u16> = slots
+        .iter()
+        .rev()
+        .flat_map(|slot| {
+            LONG_NAME_CHARS.iter().flat_map(move |&(start, end)| {
+                slot[start..end].chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            })
+        })
+        .take_while(|&unit| unit != 0)
+        .collect();
+    let name: String = char::decode_utf16(units).collect::<Result<_, _>>().ok()?;
+    (!name.is_empty()).then_some((name, checksum))
+}
 
 /// Represents a short 8.3 filename.
 #[derive(Debug, Clone, PartialEq)]
@@ -26,6 +61,11 @@ impl ShortFileName {
         Self { name: short_name }
     }
 
+    /// Raw space-padded name as stored in a directory entry.
+    pub fn as_bytes(&self) -> &[u8; 11] {
+        &self.name
+    }
+
     pub fn as_str(&self) -> String {
         format!(
             "{}.{}",
@@ -1,4 +1,6 @@
 use crate::directory::cluster::Cluster;
+use crate::directory::table::FatValue;
+use crate::error::FsError;
 use crate::filesystem::FatFileSystem;
 use crate::filesystem::StorageDevice;
 
@@ -10,7 +12,10 @@ pub struct ClusterOffsetIter {
 
 impl ClusterOffsetIter {
     pub fn new(start: Cluster, end: u32) -> Self {
-        Self { current: start, end }
+        Self {
+            current: start,
+            end,
+        }
     }
 
     pub fn next<S: StorageDevice>(&mut self, _fs: &FatFileSystem<S>) -> Option<Cluster> {
@@ -24,3 +29,43 @@ impl ClusterOffsetIter {
     }
 }
 
+/// Iterator following a cluster chain through the FAT. A FAT read error
+/// is returned as the last item rather than ending the chain early.
+pub struct ClusterChain<'a, S: StorageDevice> {
+    fs: &'a FatFileSystem<S>,
+    current: Option<Cluster>,
+    remaining: u32, // Guards against chains that loop.
+}
+
+impl<'a, S: StorageDevice> ClusterChain<'a, S> {
+    pub fn new(fs: &'a FatFileSystem<S>, start: Cluster) -> Self {
+        let current = if fs.is_data_cluster(start) {
+            Some(start)
+        } else {
+            None
+        };
+        Self {
+            fs,
+            current,
+            remaining: fs.cluster_count,
+        }
+    }
+}
+
+impl<S: StorageDevice> Iterator for ClusterChain<'_, S> {
+    type Item = Result<Cluster, FsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cluster = self.current?;
+        self.remaining = self.remaining.checked_sub(1)?;
+        self.current = match FatValue::get(self.fs, cluster) {
+            Ok(FatValue::Data(next)) if self.fs.is_data_cluster(Cluster(next)) => Some(Cluster(next)),
+            Ok(_) => None,
+            Err(err) => {
+                self.current = None;
+                return Some(Err(err));
+            }
+        };
+        Some(Ok(cluster))
+    }
+}
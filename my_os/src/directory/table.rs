@@ -1,7 +1,21 @@
 //! FAT Table Management
 
 use crate::directory::cluster::Cluster;
+use crate::error::FsError;
 use crate::filesystem::{FatFileSystem, StorageDevice};
+use crate::volume::FatType;
+use alloc::vec::Vec;
+
+/// FAT32 entries only use their low 28 bits.
+pub const FAT32_MASK: u32 = 0x0FFF_FFFF;
+
+/// FAT entry 1 flag: set when the volume was cleanly unmounted.
+pub const CLEAN_SHUTDOWN: u32 = 0x0800_0000;
+/// FAT entry 1 flag: cleared after a hard I/O error.
+pub const NO_HARD_ERROR: u32 = 0x0400_0000;
+/// FAT16 versions of the two flags above. FAT12 has none.
+pub const FAT16_CLEAN_SHUTDOWN: u32 = 0x8000;
+pub const FAT16_NO_HARD_ERROR: u32 = 0x4000;
 
 /// Represents possible values of a FAT entry.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -13,38 +27,236 @@ pub enum FatValue {
 }
 
 impl FatValue {
-    /// Retrieves the FAT entry for a given cluster.
-    pub fn get<S: StorageDevice>(fs: &FatFileSystem<S>, cluster: Cluster) -> Self {
-        let offset = cluster.to_offset(4); // FAT32 uses 4 bytes per entry.
-        let mut buffer = [0u8; 4];         // Buffer to store the read value.
-
-        // Read the FAT entry from storage.
-        if fs.storage_device.lock().read(fs.partition_start + offset, &mut buffer).is_ok() {
-            match u32::from_le_bytes(buffer) {
-                0x0000_0000 => FatValue::Free,           // Unused cluster.
-                0x0FFF_FFF8..=0x0FFF_FFFF => FatValue::EndOfChain, // End of chain marker.
-                0x0FFF_FFF7 => FatValue::Bad,            // Bad cluster marker.
-                val => FatValue::Data(val),              // Next cluster in chain.
-            }
-        } else {
-            FatValue::Bad // Return `Bad` if read fails.
+    /// Decodes a raw FAT32 entry.
+    pub fn from_raw(raw: u32) -> Self {
+        match raw & FAT32_MASK {
+            0x0000_0000 => FatValue::Free,           // Unused cluster.
+            0x0FFF_FFF8..=0x0FFF_FFFF => FatValue::EndOfChain, // End of chain marker.
+            0x0FFF_FFF7 => FatValue::Bad,            // Bad cluster marker.
+            val => FatValue::Data(val),              // Next cluster in chain.
         }
     }
 
-    /// Sets the FAT entry for a given cluster.
-    pub fn put<S: StorageDevice>(fs: &FatFileSystem<S>, cluster: Cluster, value: Self) {
-        let offset = cluster.to_offset(4); // FAT32 uses 4 bytes per entry.
-
-        // Convert `FatValue` to its corresponding raw value.
-        let raw_value = match value {
+    /// Encodes the value as a raw FAT32 entry.
+    pub fn to_raw(self) -> u32 {
+        match self {
             FatValue::Free => 0x0000_0000,
             FatValue::EndOfChain => 0x0FFF_FFFF,
             FatValue::Bad => 0x0FFF_FFF7,
             FatValue::Data(val) => val,
-        };
+        }
+    }
 
-        // Write the value into the FAT table.
-        let buffer = raw_value.to_le_bytes();
-        let _ = fs.storage_device.lock().write(fs.partition_start + offset, &buffer);
+    /// Decodes a raw entry of a FAT12, FAT16 or FAT32 table.
+    pub fn decode(raw: u32, fat_type: FatType) -> Self {
+        match fat_type {
+            FatType::Fat32 => Self::from_raw(raw),
+            // Widen the end-of-chain and bad markers to their FAT32 form.
+            FatType::Fat16 | FatType::Fat12 => {
+                let mask = fat_type.entry_mask();
+                match raw & mask {
+                    0 => FatValue::Free,
+                    raw if raw == 0x0FFF_FFF7 & mask => FatValue::Bad,
+                    raw if raw >= 0x0FFF_FFF8 & mask => FatValue::EndOfChain,
+                    val => FatValue::Data(val),
+                }
+            }
+        }
+    }
+
+    /// Encodes the value as a raw entry of a FAT12, FAT16 or FAT32 table.
+    pub fn encode(self, fat_type: FatType) -> u32 {
+        match self {
+            FatValue::Data(val) => val,
+            value => value.to_raw() & fat_type.entry_mask(),
+        }
+    }
+
+    // Byte offset of a cluster's entry within a FAT copy.
+    pub(crate) fn entry_offset(fat_type: FatType, cluster: Cluster) -> u64 {
+        let index = cluster.0 as u64;
+        match fat_type {
+            FatType::Fat12 => index + index / 2, // 1.5 bytes per entry.
+            FatType::Fat16 => index * 2,
+            FatType::Fat32 => index * 4,
+        }
+    }
+
+    // Bytes to access at `entry_offset`: FAT12 entries share a byte with a neighbour.
+    pub(crate) fn entry_width(fat_type: FatType) -> usize {
+        match fat_type {
+            FatType::Fat32 => 4,
+            FatType::Fat16 | FatType::Fat12 => 2,
+        }
+    }
+
+    // Extract a raw entry from the `entry_width` bytes at its offset.
+    pub(crate) fn unpack(fat_type: FatType, cluster: Cluster, bytes: &[u8]) -> u32 {
+        match fat_type {
+            FatType::Fat32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            FatType::Fat16 => u16::from_le_bytes([bytes[0], bytes[1]]) as u32,
+            FatType::Fat12 => {
+                let pair = u16::from_le_bytes([bytes[0], bytes[1]]) as u32;
+                // Odd clusters use the upper 12 bits of the pair.
+                if cluster.0 % 2 == 1 { pair >> 4 } else { pair & 0xFFF }
+            }
+        }
+    }
+
+    // Store a raw entry into the `entry_width` bytes at its offset,
+    // keeping the nibble shared with a FAT12 neighbour.
+    pub(crate) fn pack(fat_type: FatType, cluster: Cluster, bytes: &mut [u8], raw: u32) {
+        match fat_type {
+            FatType::Fat32 => bytes.copy_from_slice(&raw.to_le_bytes()),
+            FatType::Fat16 => bytes.copy_from_slice(&(raw as u16).to_le_bytes()),
+            FatType::Fat12 => {
+                let pair = u16::from_le_bytes([bytes[0], bytes[1]]);
+                let raw = raw as u16 & 0xFFF;
+                let pair = if cluster.0 % 2 == 1 {
+                    (pair & 0x000F) | (raw << 4)
+                } else {
+                    (pair & 0xF000) | raw
+                };
+                bytes.copy_from_slice(&pair.to_le_bytes());
+            }
+        }
+    }
+
+    /// Retrieves the FAT entry for a given cluster.
+    pub fn get<S: StorageDevice>(fs: &FatFileSystem<S>, cluster: Cluster) -> Result<Self, FsError> {
+        let raw = Self::get_raw(fs, cluster)?;
+        Ok(Self::decode(raw & FAT32_MASK, fs.fat_type))
+    }
+
+    /// Sets the FAT entry for a given cluster in every FAT copy.
+    pub fn put<S: StorageDevice>(
+        fs: &FatFileSystem<S>,
+        cluster: Cluster,
+        value: Self,
+    ) -> Result<(), FsError> {
+        let _update = fs.fat_update.lock();
+        let mut raw = value.encode(fs.fat_type);
+        if fs.fat_type == FatType::Fat32 {
+            // The upper 4 bits are reserved and must be preserved.
+            raw |= Self::get_raw(fs, cluster)? & !FAT32_MASK;
+        }
+        Self::store_raw(fs, cluster, raw)
+    }
+
+    /// Reads the raw FAT entry for a cluster from the first FAT copy.
+    /// FAT32 entries keep their reserved upper bits.
+    pub fn get_raw<S: StorageDevice>(
+        fs: &FatFileSystem<S>,
+        cluster: Cluster,
+    ) -> Result<u32, FsError> {
+        let offset = fs.fat_start + Self::entry_offset(fs.fat_type, cluster);
+        let mut buffer = [0u8; 4];
+        let bytes = &mut buffer[..Self::entry_width(fs.fat_type)];
+        fs.read_at(offset, bytes)?;
+        Ok(Self::unpack(fs.fat_type, cluster, bytes))
+    }
+
+    /// Writes a raw FAT entry, reserved bits included, into every FAT copy.
+    pub fn put_raw<S: StorageDevice>(
+        fs: &FatFileSystem<S>,
+        cluster: Cluster,
+        raw: u32,
+    ) -> Result<(), FsError> {
+        let _update = fs.fat_update.lock();
+        Self::store_raw(fs, cluster, raw)
+    }
+
+    // Write a raw entry with `fat_update` held: FAT12 entries share bytes
+    // with their neighbours, which a concurrent update must not revert.
+    fn store_raw<S: StorageDevice>(
+        fs: &FatFileSystem<S>,
+        cluster: Cluster,
+        raw: u32,
+    ) -> Result<(), FsError> {
+        let offset = fs.fat_start + Self::entry_offset(fs.fat_type, cluster);
+        let mut buffer = [0u8; 4];
+        let bytes = &mut buffer[..Self::entry_width(fs.fat_type)];
+        if fs.fat_type == FatType::Fat12 {
+            fs.read_at(offset, bytes)?;
+        }
+        Self::pack(fs.fat_type, cluster, bytes, raw);
+        for copy in 0..fs.num_fats as u64 {
+            fs.write_at(offset + copy * fs.fat_size, bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a whole FAT copy as raw entries, one per cluster including the two reserved ones.
+    /// FAT12 and FAT16 chain entries are widened to their FAT32 form.
+    pub fn load_table<S: StorageDevice>(
+        fs: &FatFileSystem<S>,
+        copy: u8,
+    ) -> Result<Vec<u32>, FsError> {
+        let entries = fs.cluster_count as usize + 2;
+        let mut bytes = vec![0u8; Self::table_bytes(fs.fat_type, entries)];
+        let mut offset = fs.fat_start + copy as u64 * fs.fat_size;
+        for chunk in bytes.chunks_mut(64 * 1024) {
+            fs.read_at(offset, chunk)?;
+            offset += chunk.len() as u64;
+        }
+
+        let table = (0..entries).map(|index| {
+            let raw = match fs.fat_type {
+                FatType::Fat32 => {
+                    let raw = &bytes[index * 4..index * 4 + 4];
+                    return u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) & FAT32_MASK;
+                }
+                FatType::Fat16 => u16::from_le_bytes([bytes[index * 2], bytes[index * 2 + 1]]),
+                FatType::Fat12 => {
+                    let at = index + index / 2;
+                    let pair = u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+                    if index % 2 == 1 { pair >> 4 } else { pair & 0xFFF }
+                }
+            };
+            match index {
+                0 | 1 => raw as u32, // Media byte and flags, not chain links.
+                _ => Self::decode(raw as u32, fs.fat_type).to_raw(),
+            }
+        });
+        Ok(table.collect())
+    }
+
+    /// Writes a table in the form returned by `load_table` into a FAT copy.
+    pub fn store_table<S: StorageDevice>(
+        fs: &FatFileSystem<S>,
+        copy: u8,
+        table: &[u32],
+    ) -> Result<(), FsError> {
+        let mut bytes = vec![0u8; Self::table_bytes(fs.fat_type, table.len())];
+        for (index, &raw) in table.iter().enumerate() {
+            let raw = match (fs.fat_type, index) {
+                (FatType::Fat32, _) | (_, 0 | 1) => raw,
+                _ => Self::from_raw(raw).encode(fs.fat_type),
+            };
+            match fs.fat_type {
+                FatType::Fat32 => bytes[index * 4..][..4].copy_from_slice(&raw.to_le_bytes()),
+                FatType::Fat16 => bytes[index * 2..][..2].copy_from_slice(&(raw as u16).to_le_bytes()),
+                FatType::Fat12 => {
+                    let at = index + index / 2;
+                    if index % 2 == 1 {
+                        bytes[at] |= (raw << 4) as u8;
+                        bytes[at + 1] = (raw >> 4) as u8;
+                    } else {
+                        bytes[at] = raw as u8;
+                        bytes[at + 1] |= (raw >> 8) as u8 & 0x0F;
+                    }
+                }
+            }
+        }
+        fs.write_at(fs.fat_start + copy as u64 * fs.fat_size, &bytes)
+    }
+
+    // Bytes taken by `entries` entries, rounded up to whole bytes.
+    fn table_bytes(fat_type: FatType, entries: usize) -> usize {
+        match fat_type {
+            FatType::Fat12 => (entries * 3).div_ceil(2),
+            FatType::Fat16 => entries * 2,
+            FatType::Fat32 => entries * 4,
+        }
     }
 }
@@ -0,0 +1,185 @@
+//! Recursive directory traversal.
+
+use crate::directory::attribute::Attributes;
+use crate::directory::cluster::Cluster;
+use crate::directory::dir_entry::{DirectoryEntry, DirectorySlots, ENTRY_SIZE};
+use crate::directory::name::{decode_long_name, short_name_checksum};
+use crate::error::FsError;
+use crate::filesystem::{FatFileSystem, StorageDevice};
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Order in which `Walk` visits entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WalkOrder {
+    /// A directory's contents follow it immediately.
+    #[default]
+    DepthFirst,
+    /// All entries at one depth come before any deeper one.
+    BreadthFirst,
+}
+
+/// Options for `FatFileSystem::walk_with`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WalkOptions {
+    pub order: WalkOrder,
+    pub max_depth: Option<usize>,    // 1 lists only the starting directory.
+    pub skip_attributes: Attributes, // Entries with any of these are neither yielded nor entered.
+}
+
+// A directory being read.
+struct Level<'a, S: StorageDevice> {
+    path: String,
+    long_path: String, // `path` with long names where entries have one.
+    slots: DirectorySlots<'a, S>,
+    depth: usize,                       // Depth of the entries it holds.
+    long_slots: Vec<[u8; ENTRY_SIZE]>, // Long name slots before the next entry.
+}
+
+/// Iterator over the entries below a directory, yielding their paths.
+///
+/// Yielded paths are made of 8.3 names, which `lookup` resolves. The same
+/// path with long names, rebuilt from the long name slots, is available
+/// from `long_path`.
+///
+/// Only the directories still being read are held in memory, plus the
+/// set of directory clusters already seen, which breaks cycles in
+/// corrupt volumes.
+pub struct Walk<'a, S: StorageDevice> {
+    fs: &'a FatFileSystem<S>,
+    options: WalkOptions,
+    levels: Vec<Level<'a, S>>, // Depth-first: open directories, innermost last.
+    queue: VecDeque<(String, String, Cluster, usize)>, // Breadth-first: directories not yet opened.
+    visited: BTreeSet<u32>,
+    long_path: String, // Of the entry yielded last.
+}
+
+impl<'a, S: StorageDevice> Walk<'a, S> {
+    fn new(fs: &'a FatFileSystem<S>, path: String, dir: Cluster, options: WalkOptions) -> Self {
+        let mut walk = Self {
+            fs,
+            options,
+            levels: Vec::new(),
+            queue: VecDeque::new(),
+            visited: BTreeSet::new(),
+            long_path: String::new(),
+        };
+        walk.visited.insert(dir.0);
+        walk.enter(path.clone(), path, dir, 1);
+        walk
+    }
+
+    /// The path of the entry yielded last, with long names where entries
+    /// have one. It has as many components as the yielded path.
+    pub fn long_path(&self) -> &str {
+        &self.long_path
+    }
+
+    fn enter(&mut self, path: String, long_path: String, dir: Cluster, depth: usize) {
+        if self.options.max_depth.is_some_and(|max| depth > max) {
+            return;
+        }
+        match self.options.order {
+            WalkOrder::DepthFirst => self.levels.push(Level::new(self.fs, path, long_path, dir, depth)),
+            WalkOrder::BreadthFirst => self.queue.push_back((path, long_path, dir, depth)),
+        }
+    }
+
+    // The directory to read from next, opening a queued one if needed.
+    fn current(&mut self) -> Option<&mut Level<'a, S>> {
+        if self.levels.is_empty() {
+            let (path, long_path, dir, depth) = self.queue.pop_front()?;
+            self.levels.push(Level::new(self.fs, path, long_path, dir, depth));
+        }
+        self.levels.last_mut()
+    }
+}
+
+impl<'a, S: StorageDevice> Level<'a, S> {
+    fn new(fs: &'a FatFileSystem<S>, path: String, long_path: String, dir: Cluster, depth: usize) -> Self {
+        Self {
+            path,
+            long_path,
+            slots: DirectorySlots::new(fs, dir),
+            depth,
+            long_slots: Vec::new(),
+        }
+    }
+}
+
+impl<S: StorageDevice> Iterator for Walk<'_, S> {
+    type Item = Result<(String, DirectoryEntry), FsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let skip_attributes = self.options.skip_attributes;
+        loop {
+            let level = self.current()?;
+            let slot = match level.slots.next() {
+                Some(Ok(slot)) if !slot.is_end() => slot,
+                Some(Err(err)) => {
+                    self.levels.pop();
+                    return Some(Err(err));
+                }
+                _ => {
+                    self.levels.pop();
+                    continue;
+                }
+            };
+            if slot.is_deleted() {
+                level.long_slots.clear();
+                continue;
+            }
+            if slot.is_long_name() {
+                level.long_slots.push(slot.bytes);
+                continue;
+            }
+            let entry = slot.entry();
+            let long_slots = core::mem::take(&mut level.long_slots);
+            if entry.is_volume_label() || entry.is_dot() || entry.attributes.intersects(skip_attributes) {
+                continue;
+            }
+
+            // Slots left by a driver unaware of long names carry a stale checksum.
+            let name = match decode_long_name(&long_slots) {
+                Some((name, checksum)) if checksum == short_name_checksum(&entry.file_name) => name,
+                _ => entry.file_name(),
+            };
+            let path = format!("{}/{}", level.path, entry.file_name());
+            let long_path = format!("{}/{}", level.long_path, name);
+            let depth = level.depth;
+            if entry.is_directory() {
+                let dir = self.fs.directory_cluster(&entry);
+                // A directory met twice is a cycle (or a cross-link); don't enter it again.
+                if self.visited.insert(dir.0) {
+                    self.enter(path.clone(), long_path.clone(), dir, depth + 1);
+                }
+            }
+            self.long_path = long_path;
+            return Some(Ok((path, entry)));
+        }
+    }
+}
+
+impl<S: StorageDevice> FatFileSystem<S> {
+    /// Walk the tree below the directory at `path` depth-first.
+    pub fn walk(&self, path: &str) -> Result<Walk<'_, S>, FsError> {
+        self.walk_with(path, WalkOptions::default())
+    }
+
+    /// Walk the tree below the directory at `path` with explicit options.
+    pub fn walk_with(&self, path: &str, options: WalkOptions) -> Result<Walk<'_, S>, FsError> {
+        let dir = self.lookup(path)?;
+        if !dir.is_directory() {
+            return Err(FsError::InvalidArgument);
+        }
+        // Yielded paths are absolute, without a trailing slash.
+        let mut base = String::new();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            base.push('/');
+            base.push_str(name);
+        }
+        Ok(Walk::new(self, base, self.directory_cluster(&dir), options))
+    }
+}
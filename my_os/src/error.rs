@@ -0,0 +1,47 @@
+//! Error type shared by the storage, partition and filesystem layers.
+
+use core::fmt;
+
+/// Errors reported by the filesystem crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    Io,                    // The storage device failed to complete the request.
+    OutOfBounds,           // Access outside the device or partition.
+    ReadOnly,              // The device or volume does not accept writes.
+    InvalidPartitionTable, // Missing signature or inconsistent partition entries.
+    NotFound,              // The requested item does not exist.
+    InvalidArgument,       // Parameters rejected, e.g. a volume too small to format.
+    InvalidBootSector,     // Missing signature or inconsistent BPB fields.
+    AlreadyExists,         // An entry with that name already exists.
+    NoSpace,               // No free cluster left.
+    DirtyVolume,           // The volume was not cleanly unmounted and needs checking.
+    IsDirectory,           // A file operation was used on a directory.
+    DirectoryNotEmpty,     // The directory still has entries.
+    AccessDenied,          // The entry is read-only or in use by the filesystem.
+    Busy,                  // The file is open, or open in a conflicting mode.
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            FsError::Io => "I/O error",
+            FsError::OutOfBounds => "access out of bounds",
+            FsError::ReadOnly => "read-only",
+            FsError::InvalidPartitionTable => "invalid partition table",
+            FsError::NotFound => "not found",
+            FsError::InvalidArgument => "invalid argument",
+            FsError::InvalidBootSector => "invalid boot sector",
+            FsError::AlreadyExists => "already exists",
+            FsError::NoSpace => "no space left on volume",
+            FsError::DirtyVolume => "volume not cleanly unmounted",
+            FsError::IsDirectory => "is a directory",
+            FsError::DirectoryNotEmpty => "directory not empty",
+            FsError::AccessDenied => "access denied",
+            FsError::Busy => "file in use",
+        };
+        f.write_str(message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FsError {}
@@ -0,0 +1,133 @@
+//! exFAT boot region: main boot sector and its checksum.
+
+use crate::boot_sector::BOOT_SIGNATURE;
+use crate::error::FsError;
+use crate::filesystem::StorageDevice;
+
+/// File system name at offset 3 of the boot sector.
+const FS_NAME: &[u8; 8] = b"EXFAT   ";
+/// Sectors in a boot region: boot, 8 extended boot, OEM, reserved, checksum.
+pub const BOOT_REGION_SECTORS: u64 = 12;
+/// Highest cluster count, keeping cluster numbers below the FAT markers.
+pub const MAX_CLUSTER_COUNT: u32 = 0xFFFF_FFF5;
+
+fn le_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Main boot sector fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExFatBootSector {
+    pub partition_offset: u64, // In sectors, informational.
+    pub volume_length: u64,    // In sectors.
+    pub fat_offset: u32,       // In sectors.
+    pub fat_length: u32,       // In sectors.
+    pub cluster_heap_offset: u32,
+    pub cluster_count: u32,
+    pub root_cluster: u32,
+    pub volume_serial: u32,
+    pub revision: u16, // Major in the high byte.
+    pub volume_flags: u16,
+    pub bytes_per_sector_shift: u8,
+    pub sectors_per_cluster_shift: u8,
+    pub num_fats: u8,
+}
+
+impl ExFatBootSector {
+    /// Parse and sanity-check a main boot sector.
+    pub fn parse(sector: &[u8]) -> Result<Self, FsError> {
+        if sector.len() < 512 || sector[510..512] != BOOT_SIGNATURE || &sector[3..11] != FS_NAME {
+            return Err(FsError::InvalidBootSector);
+        }
+        // The BPB area of FAT volumes must be zero on exFAT.
+        if sector[11..64].iter().any(|&byte| byte != 0) {
+            return Err(FsError::InvalidBootSector);
+        }
+
+        let boot = Self {
+            partition_offset: le_u64(sector, 64),
+            volume_length: le_u64(sector, 72),
+            fat_offset: le_u32(sector, 80),
+            fat_length: le_u32(sector, 84),
+            cluster_heap_offset: le_u32(sector, 88),
+            cluster_count: le_u32(sector, 92),
+            root_cluster: le_u32(sector, 96),
+            volume_serial: le_u32(sector, 100),
+            revision: u16::from_le_bytes([sector[104], sector[105]]),
+            volume_flags: u16::from_le_bytes([sector[106], sector[107]]),
+            bytes_per_sector_shift: sector[108],
+            sectors_per_cluster_shift: sector[109],
+            num_fats: sector[110],
+        };
+
+        // Summed in 64 bits: crafted fields must not wrap around.
+        let fat_end = boot.fat_offset as u64 + boot.fat_length as u64 * boot.num_fats as u64;
+        let valid = (9..=12).contains(&boot.bytes_per_sector_shift)
+            && boot.bytes_per_sector_shift + boot.sectors_per_cluster_shift <= 25
+            && matches!(boot.num_fats, 1 | 2)
+            && boot.revision >> 8 == 1
+            && boot.fat_offset >= 24
+            && boot.cluster_heap_offset as u64 >= fat_end
+            && boot.cluster_count <= MAX_CLUSTER_COUNT
+            && boot.root_cluster >= 2
+            && (boot.root_cluster as u64) < boot.cluster_count as u64 + 2;
+        if !valid {
+            return Err(FsError::InvalidBootSector);
+        }
+        Ok(boot)
+    }
+
+    pub fn bytes_per_sector(&self) -> u64 {
+        1 << self.bytes_per_sector_shift
+    }
+
+    pub fn cluster_size(&self) -> u32 {
+        1 << (self.bytes_per_sector_shift + self.sectors_per_cluster_shift)
+    }
+}
+
+/// Checksum of the first 11 sectors of a boot region. The volume flags and
+/// percent-in-use bytes of the boot sector change at runtime and are skipped.
+pub fn boot_checksum(region: &[u8], bytes_per_sector: usize) -> u32 {
+    region[..11 * bytes_per_sector]
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| !matches!(i, 106 | 107 | 112))
+        .fold(0u32, |sum, (_, &byte)| sum.rotate_right(1).wrapping_add(byte as u32))
+}
+
+/// Read and validate the boot region starting at `offset`.
+fn read_region<S: StorageDevice>(device: &S, offset: u64) -> Result<ExFatBootSector, FsError> {
+    let mut sector = [0u8; 512];
+    device.read(offset, &mut sector)?;
+    let boot = ExFatBootSector::parse(&sector)?;
+
+    let sector_size = boot.bytes_per_sector() as usize;
+    let mut region = vec![0u8; BOOT_REGION_SECTORS as usize * sector_size];
+    device.read(offset, &mut region)?;
+    let checksum = boot_checksum(&region, sector_size);
+    // The checksum sector repeats the checksum to fill the sector.
+    let stored = &region[11 * sector_size..];
+    if stored.chunks_exact(4).any(|word| le_u32(word, 0) != checksum) {
+        return Err(FsError::InvalidBootSector);
+    }
+    Ok(boot)
+}
+
+/// Read the main boot region at `offset`, falling back to the backup region
+/// that follows it.
+pub fn read_boot<S: StorageDevice>(device: &S, offset: u64) -> Result<ExFatBootSector, FsError> {
+    read_region(device, offset).or_else(|err| {
+        // The backup starts 12 sectors in; try each legal sector size.
+        (9..=12)
+            .find_map(|shift| {
+                let boot = read_region(device, offset + (BOOT_REGION_SECTORS << shift)).ok()?;
+                (boot.bytes_per_sector_shift == shift).then_some(boot)
+            })
+            .ok_or(err)
+    })
+}
@@ -0,0 +1,122 @@
+//! exFAT directory entries and file entry sets.
+//!
+//! A file is described by a set of consecutive 32-byte entries: a file entry
+//! holding the attributes and the set checksum, a stream extension with the
+//! size and first cluster, then one name entry per 15 UTF-16 characters.
+
+use crate::directory::cluster::Cluster;
+use crate::error::FsError;
+use crate::exfat::upcase::UpcaseTable;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Size of a directory entry.
+pub const ENTRY_SIZE: usize = 32;
+
+pub const END_OF_DIRECTORY: u8 = 0x00;
+pub const ALLOCATION_BITMAP: u8 = 0x81;
+pub const UPCASE_TABLE: u8 = 0x82;
+pub const VOLUME_LABEL: u8 = 0x83;
+pub const FILE: u8 = 0x85;
+pub const STREAM_EXTENSION: u8 = 0xC0;
+pub const FILE_NAME: u8 = 0xC1;
+
+/// Stream flag: the data occupies consecutive clusters and the FAT is not used.
+pub const NO_FAT_CHAIN: u8 = 0x02;
+/// UTF-16 characters held by one file name entry.
+pub const NAME_CHARS: usize = 15;
+
+fn le_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn le_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// First cluster and length of the data described by a bitmap, up-case or stream entry.
+pub fn data_location(entry: &[u8]) -> (Cluster, u64) {
+    (Cluster(le_u32(entry, 20)), le_u64(entry, 24))
+}
+
+/// Checksum of the up-case table recorded in its entry.
+pub fn upcase_checksum(entry: &[u8]) -> u32 {
+    le_u32(entry, 4)
+}
+
+/// Text of a volume label entry.
+pub fn label_text(entry: &[u8]) -> String {
+    let count = (entry[1] as usize).min(11);
+    let units: Vec<u16> = (0..count).map(|i| le_u16(entry, 2 + 2 * i)).collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Checksum over an entry set, skipping the checksum field itself.
+pub fn set_checksum(set: &[u8]) -> u16 {
+    set.iter()
+        .enumerate()
+        .filter(|&(i, _)| i != 2 && i != 3)
+        .fold(0u16, |sum, (_, &byte)| sum.rotate_right(1).wrapping_add(byte as u16))
+}
+
+/// A file or directory as described by its entry set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExFatEntry {
+    pub name: String,
+    pub attributes: u16,
+    pub first_cluster: Cluster,
+    pub data_length: u64,       // Allocated size of the data.
+    pub valid_data_length: u64, // Bytes written; the rest reads as zeros.
+    pub no_fat_chain: bool,     // The clusters are contiguous.
+}
+
+impl ExFatEntry {
+    pub fn is_directory(&self) -> bool {
+        self.attributes & crate::directory::attribute::Attributes::DIRECTORY.bits() as u16 != 0
+    }
+
+    /// Parse a complete entry set, checking its checksum and name hash.
+    pub fn parse_set(set: &[u8], upcase: &UpcaseTable) -> Result<Self, FsError> {
+        let secondary = set[1] as usize;
+        if set[0] != FILE || secondary < 2 || set.len() != (secondary + 1) * ENTRY_SIZE {
+            return Err(FsError::Io);
+        }
+        if le_u16(set, 2) != set_checksum(set) {
+            return Err(FsError::Io); // Torn or corrupt entry set.
+        }
+
+        let stream = &set[ENTRY_SIZE..2 * ENTRY_SIZE];
+        if stream[0] != STREAM_EXTENSION {
+            return Err(FsError::Io);
+        }
+        let name_length = stream[3] as usize;
+        let mut name: Vec<u16> = Vec::with_capacity(name_length);
+        for entry in set[2 * ENTRY_SIZE..].chunks_exact(ENTRY_SIZE) {
+            if entry[0] != FILE_NAME {
+                break; // Vendor extensions may follow the name.
+            }
+            name.extend((0..NAME_CHARS).map(|i| le_u16(entry, 2 + 2 * i)));
+        }
+        if name_length == 0 || name.len() < name_length {
+            return Err(FsError::Io);
+        }
+        name.truncate(name_length);
+        if upcase.name_hash(&name) != le_u16(stream, 4) {
+            return Err(FsError::Io);
+        }
+
+        let (first_cluster, data_length) = data_location(stream);
+        Ok(Self {
+            name: String::from_utf16_lossy(&name),
+            attributes: le_u16(set, 4),
+            first_cluster,
+            data_length,
+            valid_data_length: le_u64(stream, 8).min(data_length),
+            no_fat_chain: stream[1] & NO_FAT_CHAIN != 0,
+        })
+    }
+}
@@ -0,0 +1,300 @@
+//! Read-only exFAT driver.
+//!
+//! exFAT shares the cluster model of FAT but keeps free space in an
+//! allocation bitmap, stores names in UTF-16 entry sets protected by a
+//! checksum, and lets contiguous files skip the FAT (`NoFatChain`).
+
+pub mod boot;
+pub mod entry;
+pub mod upcase;
+
+pub use boot::ExFatBootSector;
+pub use entry::ExFatEntry;
+pub use upcase::UpcaseTable;
+
+use crate::directory::attribute::Attributes;
+use crate::directory::cluster::Cluster;
+use crate::error::FsError;
+use crate::filesystem::StorageDevice;
+use alloc::string::String;
+use alloc::vec::Vec;
+use entry::{ENTRY_SIZE, END_OF_DIRECTORY, FILE};
+use spin::Mutex;
+
+/// FAT entries from this value up mark bad clusters and the end of a chain.
+const BAD_CLUSTER: u32 = 0xFFFF_FFF7;
+
+/// A mounted exFAT volume.
+pub struct ExFatFileSystem<S: StorageDevice> {
+    pub storage_device: Mutex<S>,
+    pub partition_start: u64,
+    pub cluster_size: u32,
+    pub fat_start: u64,  // Active FAT, relative to the partition start.
+    pub data_start: u64, // Cluster heap, relative to the partition start.
+    pub cluster_count: u32,
+    pub root_cluster: Cluster,
+    pub volume_serial: u32,
+    pub label: Option<String>,
+    upcase: UpcaseTable,
+    bitmap: Vec<u8>, // One bit per cluster, starting at cluster 2.
+}
+
+impl<S: StorageDevice> ExFatFileSystem<S> {
+    /// Mount an exFAT volume starting at offset zero of `storage_device`.
+    pub fn mount(storage_device: S) -> Result<Self, FsError> {
+        Self::mount_at(storage_device, 0)
+    }
+
+    /// Mount an exFAT volume whose boot region is at `partition_start`.
+    ///
+    /// The boot region checksum is verified, falling back to the backup
+    /// region. The root directory must hold an allocation bitmap; the
+    /// up-case table is checked against its checksum.
+    pub fn mount_at(storage_device: S, partition_start: u64) -> Result<Self, FsError> {
+        let boot = boot::read_boot(&storage_device, partition_start)?;
+        let sector_size = boot.bytes_per_sector();
+        let active_fat = (boot.volume_flags & 1) as u64 & (boot.num_fats as u64 - 1);
+
+        let mut fs = Self {
+            storage_device: Mutex::new(storage_device),
+            partition_start,
+            cluster_size: boot.cluster_size(),
+            fat_start: (boot.fat_offset as u64 + active_fat * boot.fat_length as u64) * sector_size,
+            data_start: boot.cluster_heap_offset as u64 * sector_size,
+            cluster_count: boot.cluster_count,
+            root_cluster: Cluster(boot.root_cluster),
+            volume_serial: boot.volume_serial,
+            label: None,
+            upcase: UpcaseTable::ascii(),
+            bitmap: Vec::new(),
+        };
+
+        let root = fs.read_stream(&fs.root())?;
+        let mut bitmap = None;
+        for slot in root.chunks_exact(ENTRY_SIZE) {
+            match slot[0] {
+                END_OF_DIRECTORY => break,
+                // With two FATs there are two bitmaps; flag bit 0 picks the second.
+                entry::ALLOCATION_BITMAP if (slot[1] & 1) as u64 == active_fat => {
+                    bitmap = Some(entry::data_location(slot));
+                }
+                entry::UPCASE_TABLE => {
+                    let (first, length) = entry::data_location(slot);
+                    let table = fs.read_metadata(first, length)?;
+                    if upcase::table_checksum(&table) != entry::upcase_checksum(slot) {
+                        return Err(FsError::Io);
+                    }
+                    fs.upcase = UpcaseTable::parse(&table).ok_or(FsError::Io)?;
+                }
+                entry::VOLUME_LABEL => fs.label = Some(entry::label_text(slot)),
+                _ => {}
+            }
+        }
+
+        let (first, length) = bitmap.ok_or(FsError::InvalidBootSector)?;
+        if length < (fs.cluster_count as u64).div_ceil(8) {
+            return Err(FsError::InvalidBootSector);
+        }
+        fs.bitmap = fs.read_metadata(first, length)?;
+        Ok(fs)
+    }
+
+    /// Read bytes at `offset`, relative to the partition start.
+    pub fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<(), FsError> {
+        self.storage_device.lock().read(self.partition_start + offset, buffer)
+    }
+
+    /// Byte offset of a cluster, relative to the partition start.
+    pub fn cluster_offset(&self, cluster: Cluster) -> u64 {
+        self.data_start + cluster.to_offset(self.cluster_size)
+    }
+
+    /// Check if `cluster` lies in the cluster heap.
+    pub fn is_data_cluster(&self, cluster: Cluster) -> bool {
+        cluster.0 >= 2 && cluster.0 < self.cluster_count + 2
+    }
+
+    /// Follow the FAT from `cluster`, `None` at the end of the chain.
+    pub fn next_cluster(&self, cluster: Cluster) -> Result<Option<Cluster>, FsError> {
+        let mut raw = [0u8; 4];
+        self.read_at(self.fat_start + cluster.0 as u64 * 4, &mut raw)?;
+        match u32::from_le_bytes(raw) {
+            next if next >= BAD_CLUSTER => Ok(None),
+            next if self.is_data_cluster(Cluster(next)) => Ok(Some(Cluster(next))),
+            _ => Err(FsError::Io),
+        }
+    }
+
+    /// Check the allocation bitmap for `cluster`.
+    pub fn is_allocated(&self, cluster: Cluster) -> bool {
+        if !self.is_data_cluster(cluster) {
+            return false;
+        }
+        let index = (cluster.0 - 2) as usize;
+        self.bitmap[index / 8] & (1 << (index % 8)) != 0
+    }
+
+    /// Count the clusters marked free in the allocation bitmap.
+    pub fn free_clusters(&self) -> u32 {
+        (2..self.cluster_count + 2)
+            .filter(|&cluster| !self.is_allocated(Cluster(cluster)))
+            .count() as u32
+    }
+
+    /// The up-case table used for name lookups.
+    pub fn upcase(&self) -> &UpcaseTable {
+        &self.upcase
+    }
+
+    /// The root directory, which has no entry set of its own.
+    pub fn root(&self) -> ExFatEntry {
+        ExFatEntry {
+            name: String::new(),
+            attributes: Attributes::DIRECTORY.bits() as u16,
+            first_cluster: self.root_cluster,
+            data_length: u64::MAX, // Its length is that of its FAT chain.
+            valid_data_length: u64::MAX,
+            no_fat_chain: false,
+        }
+    }
+
+    /// Clusters holding the first `count` clusters of `entry`'s data.
+    /// Data without a first cluster is corrupt.
+    fn clusters(&self, entry: &ExFatEntry, count: u64) -> Result<Vec<Cluster>, FsError> {
+        let first = entry.first_cluster;
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        if first.0 == 0 {
+            return Err(FsError::Io);
+        }
+        if entry.no_fat_chain {
+            let last = first.0 as u64 + count - 1;
+            if !self.is_data_cluster(first) || last >= self.cluster_count as u64 + 2 {
+                return Err(FsError::Io);
+            }
+            return Ok((first.0..=last as u32).map(Cluster).collect());
+        }
+
+        let mut clusters = Vec::new();
+        let mut current = self.is_data_cluster(first).then_some(first);
+        while let Some(cluster) = current {
+            if clusters.len() as u64 == count {
+                break;
+            }
+            if clusters.len() as u32 == self.cluster_count {
+                return Err(FsError::Io); // The chain loops.
+            }
+            clusters.push(cluster);
+            current = self.next_cluster(cluster)?;
+        }
+        // Only the root directory may end wherever its chain ends.
+        if count != u64::MAX && (clusters.len() as u64) < count {
+            return Err(FsError::Io);
+        }
+        Ok(clusters)
+    }
+
+    // Whole data of a directory or metadata stream.
+    fn read_stream(&self, entry: &ExFatEntry) -> Result<Vec<u8>, FsError> {
+        let count = match entry.data_length {
+            u64::MAX => u64::MAX,
+            length => length.div_ceil(self.cluster_size as u64),
+        };
+        let clusters = self.clusters(entry, count)?;
+        let mut data = vec![0u8; clusters.len() * self.cluster_size as usize];
+        for (cluster, chunk) in clusters.iter().zip(data.chunks_mut(self.cluster_size as usize)) {
+            self.read_at(self.cluster_offset(*cluster), chunk)?;
+        }
+        data.truncate(entry.valid_data_length.min(data.len() as u64) as usize);
+        Ok(data)
+    }
+
+    // Data of the bitmap and up-case table, which are chained in the FAT.
+    fn read_metadata(&self, first: Cluster, length: u64) -> Result<Vec<u8>, FsError> {
+        let entry = ExFatEntry {
+            name: String::new(),
+            attributes: 0,
+            first_cluster: first,
+            data_length: length,
+            valid_data_length: length,
+            no_fat_chain: false,
+        };
+        self.read_stream(&entry)
+    }
+
+    /// List the files and directories of `dir`, validating every entry set.
+    pub fn read_dir(&self, dir: &ExFatEntry) -> Result<Vec<ExFatEntry>, FsError> {
+        if !dir.is_directory() {
+            return Err(FsError::InvalidArgument);
+        }
+        let data = self.read_stream(dir)?;
+        let mut entries = Vec::new();
+        let mut position = 0;
+        while position + ENTRY_SIZE <= data.len() {
+            let slot = &data[position..position + ENTRY_SIZE];
+            if slot[0] == END_OF_DIRECTORY {
+                break;
+            }
+            if slot[0] != FILE {
+                position += ENTRY_SIZE;
+                continue;
+            }
+            let end = position + (slot[1] as usize + 1) * ENTRY_SIZE;
+            let set = data.get(position..end).ok_or(FsError::Io)?;
+            entries.push(ExFatEntry::parse_set(set, &self.upcase)?);
+            position = end;
+        }
+        Ok(entries)
+    }
+
+    /// Find `name` in `dir`, ignoring case as the up-case table defines it.
+    pub fn find_entry(&self, dir: &ExFatEntry, name: &str) -> Result<ExFatEntry, FsError> {
+        let wanted: Vec<u16> = name.encode_utf16().collect();
+        self.read_dir(dir)?
+            .into_iter()
+            .find(|entry| {
+                let units: Vec<u16> = entry.name.encode_utf16().collect();
+                self.upcase.eq_ignore_case(&units, &wanted)
+            })
+            .ok_or(FsError::NotFound)
+    }
+
+    /// Resolve a `/`-separated path from the root directory.
+    pub fn open(&self, path: &str) -> Result<ExFatEntry, FsError> {
+        let mut current = self.root();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if !current.is_directory() {
+                return Err(FsError::NotFound);
+            }
+            current = self.find_entry(&current, name)?;
+        }
+        Ok(current)
+    }
+
+    /// Read file data at `offset`, returning the number of bytes read (0 at the end).
+    /// Bytes past the valid data length read as zeros.
+    pub fn read(&self, file: &ExFatEntry, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let len = (buffer.len() as u64).min(file.data_length.saturating_sub(offset)) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        let buffer = &mut buffer[..len];
+        let valid_end = file.valid_data_length.clamp(offset, offset + len as u64);
+        let (valid, zeros) = buffer.split_at_mut((valid_end - offset) as usize);
+        zeros.fill(0);
+
+        let cluster_size = self.cluster_size as u64;
+        let clusters = self.clusters(file, valid_end.div_ceil(cluster_size))?;
+        let mut done = 0;
+        while done < valid.len() {
+            let position = offset + done as u64;
+            let within = position % cluster_size;
+            let count = ((cluster_size - within) as usize).min(valid.len() - done);
+            let cluster = clusters[(position / cluster_size) as usize];
+            self.read_at(self.cluster_offset(cluster) + within, &mut valid[done..done + count])?;
+            done += count;
+        }
+        Ok(len)
+    }
+}
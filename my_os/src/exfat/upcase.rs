@@ -0,0 +1,69 @@
+//! exFAT up-case table, used for case-insensitive names and name hashes.
+
+use alloc::vec::Vec;
+
+/// Marker of a compressed run of identity mappings.
+const IDENTITY_RUN: u16 = 0xFFFF;
+
+/// Full mapping from every UTF-16 code unit to its upper-case form.
+pub struct UpcaseTable {
+    map: Vec<u16>,
+}
+
+impl UpcaseTable {
+    /// Table mapping only ASCII letters, for volumes without a usable table.
+    pub fn ascii() -> Self {
+        let map = (0..=u16::MAX)
+            .map(|unit| match unit {
+                0x61..=0x7A => unit - 0x20,
+                _ => unit,
+            })
+            .collect();
+        Self { map }
+    }
+
+    /// Decode an on-disk table, expanding compressed identity runs.
+    /// Returns `None` if the table is malformed.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let mut map = Vec::with_capacity(0x10000);
+        let mut units = bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+        while let Some(unit) = units.next() {
+            if unit == IDENTITY_RUN {
+                let count = units.next()? as usize;
+                let start = map.len();
+                map.extend((start..start + count).map(|i| i as u16));
+            } else {
+                map.push(unit);
+            }
+            if map.len() > 0x10000 {
+                return None;
+            }
+        }
+        // Units beyond the end of the table map to themselves.
+        let start = map.len();
+        map.extend((start..0x10000).map(|i| i as u16));
+        Some(Self { map })
+    }
+
+    /// Upper-case form of a UTF-16 code unit.
+    pub fn upcase(&self, unit: u16) -> u16 {
+        self.map[unit as usize]
+    }
+
+    /// Hash of a name as stored in its stream extension entry.
+    pub fn name_hash(&self, name: &[u16]) -> u16 {
+        name.iter()
+            .flat_map(|&unit| self.upcase(unit).to_le_bytes())
+            .fold(0u16, |hash, byte| hash.rotate_right(1).wrapping_add(byte as u16))
+    }
+
+    /// Compare two names ignoring case.
+    pub fn eq_ignore_case(&self, a: &[u16], b: &[u16]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(&x, &y)| self.upcase(x) == self.upcase(y))
+    }
+}
+
+/// Checksum of the up-case table as stored in its directory entry.
+pub fn table_checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0u32, |sum, &byte| sum.rotate_right(1).wrapping_add(byte as u32))
+}
@@ -0,0 +1,276 @@
+//! Regular files stored in a FAT32 volume.
+//!
+//! Writes follow a fixed order so that a crash leaves at worst lost or
+//! overlong chains for `fsck` to clean up, never an entry pointing at
+//! clusters that are not part of its chain:
+//! allocate and link clusters, write the data, then update the entry.
+
+use crate::directory::attribute::Attributes;
+use crate::directory::cluster::Cluster;
+use crate::directory::dir_entry::DirectoryEntry;
+use crate::directory::table::FatValue;
+use crate::error::FsError;
+use crate::filesystem::{FatFileSystem, StorageDevice};
+use crate::lock::OpenMode;
+use alloc::vec::Vec;
+
+/// A run of consecutive clusters in a file's chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    pub start: Cluster,
+    pub length: u32, // In clusters.
+}
+
+/// An open file with its own read/write position.
+/// The handle keeps its sharing mode registered until dropped.
+pub struct File<'a, S: StorageDevice> {
+    fs: &'a FatFileSystem<S>,
+    entry: DirectoryEntry,
+    entry_offset: u64, // Slot of the entry, relative to the partition start.
+    position: u32,
+    mode: OpenMode,
+}
+
+impl<'a, S: StorageDevice> File<'a, S> {
+    /// Open the entry at `entry_offset`, failing with `Busy` if `mode`
+    /// conflicts with the handles already open on it.
+    pub(crate) fn open(
+        fs: &'a FatFileSystem<S>,
+        entry: DirectoryEntry,
+        entry_offset: u64,
+        mode: OpenMode,
+    ) -> Result<Self, FsError> {
+        fs.open_files.acquire(entry_offset, mode)?;
+        Ok(Self {
+            fs,
+            entry,
+            entry_offset,
+            position: 0,
+            mode,
+        })
+    }
+
+    pub(crate) fn fs(&self) -> &'a FatFileSystem<S> {
+        self.fs
+    }
+
+    pub(crate) fn entry_offset(&self) -> u64 {
+        self.entry_offset
+    }
+
+    /// The directory entry as last written.
+    pub fn entry(&self) -> &DirectoryEntry {
+        &self.entry
+    }
+
+    /// The mode the file was opened in.
+    pub fn mode(&self) -> OpenMode {
+        self.mode
+    }
+
+    /// Size of the file in bytes.
+    pub fn len(&self) -> u32 {
+        self.entry.file_size
+    }
+
+    /// Check if the file holds no data.
+    pub fn is_empty(&self) -> bool {
+        self.entry.file_size == 0
+    }
+
+    /// Current read/write position.
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// Move the read/write position. Writing past the end fills the gap with zeros.
+    pub fn seek(&mut self, position: u32) {
+        self.position = position;
+    }
+
+    /// Read from the current position, returning the number of bytes read (0 at the end).
+    /// The last access date is not maintained: reads never write to the volume.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let available = self.entry.file_size.saturating_sub(self.position) as usize;
+        let len = buffer.len().min(available);
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let cluster_size = self.fs.cluster_size as usize;
+        let first = self.position as usize / cluster_size;
+        let mut done = 0;
+        for cluster in self.fs.chain(self.entry.start_cluster).skip(first) {
+            if done == len {
+                break;
+            }
+            let cluster = cluster?;
+            let within = (self.position as usize + done) % cluster_size;
+            let count = (cluster_size - within).min(len - done);
+            let offset = self.fs.cluster_offset(cluster) + within as u64;
+            self.fs.read_at(offset, &mut buffer[done..done + count])?;
+            done += count;
+        }
+        if done < len {
+            return Err(FsError::Io); // The chain is shorter than the file size.
+        }
+
+        self.position += len as u32;
+        Ok(len)
+    }
+
+    /// Write at the current position, growing the file as needed.
+    /// Fails with `AccessDenied` if the file is read-only or opened for reading.
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, FsError> {
+        if self.mode == OpenMode::Read || self.entry.attributes.is_read_only() {
+            return Err(FsError::AccessDenied);
+        }
+        let fs = self.fs;
+        fs.transaction(|| self.write_inner(data))
+    }
+
+    fn write_inner(&mut self, data: &[u8]) -> Result<usize, FsError> {
+        let size = self.entry.file_size;
+        let end = (self.position as u64 + data.len() as u64)
+            .try_into()
+            .map_err(|_| FsError::InvalidArgument)?;
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        // 1. Allocate and link the clusters the new end needs.
+        let clusters = self.reserve(end)?;
+        self.fs.barrier()?;
+
+        // 2. Write the data, zero-filling any gap after the old end.
+        self.zero_range(&clusters, size, self.position)?;
+        self.write_range(&clusters, self.position, data)?;
+        self.fs.barrier()?;
+
+        // 3. Publish the new size in the directory entry.
+        self.entry.file_size = self.entry.file_size.max(end);
+        self.entry.attributes |= Attributes::ARCHIVE;
+        self.fs.update_entry(self.entry_offset, &self.entry)?;
+        self.fs.barrier()?;
+
+        self.position = end;
+        Ok(data.len())
+    }
+
+    /// Reserve clusters for `len` bytes up front and grow the file to `len`.
+    /// The clusters are taken as one contiguous run where possible.
+    ///
+    /// FAT cannot record clusters past the end of a file, so the size grows
+    /// too. With `zero_fill` the new bytes read as zeros; without it they
+    /// keep whatever the clusters held before, which is faster for callers
+    /// that overwrite the whole range anyway. Never shrinks the file.
+    pub fn allocate(&mut self, len: u32, zero_fill: bool) -> Result<(), FsError> {
+        if self.mode == OpenMode::Read || self.entry.attributes.is_read_only() {
+            return Err(FsError::AccessDenied);
+        }
+        if len <= self.entry.file_size {
+            return Ok(());
+        }
+        let fs = self.fs;
+        fs.transaction(|| {
+            let clusters = self.reserve(len)?;
+            self.fs.barrier()?;
+
+            if zero_fill {
+                self.zero_range(&clusters, self.entry.file_size, len)?;
+                self.fs.barrier()?;
+            }
+
+            self.entry.file_size = len;
+            self.entry.attributes |= Attributes::ARCHIVE;
+            self.fs.update_entry(self.entry_offset, &self.entry)?;
+            self.fs.barrier()
+        })
+    }
+
+    /// The runs of consecutive clusters holding the file, in file order.
+    /// More than one extent means the file is fragmented.
+    pub fn extents(&self) -> Result<Vec<Extent>, FsError> {
+        if self.entry.start_cluster.0 == 0 {
+            return Ok(Vec::new());
+        }
+        let mut extents: Vec<Extent> = Vec::new();
+        for cluster in self.fs.chain(self.entry.start_cluster) {
+            let cluster = cluster?;
+            match extents.last_mut() {
+                Some(extent) if extent.start.0 + extent.length == cluster.0 => extent.length += 1,
+                _ => extents.push(Extent { start: cluster, length: 1 }),
+            }
+        }
+        Ok(extents)
+    }
+
+    // Grow the chain to hold `len` bytes and return all of its clusters.
+    // New clusters continue the chain contiguously where there is room.
+    // They are chained among themselves before a single FAT write
+    // attaches them, so a crash leaves either no extension or a whole one.
+    fn reserve(&mut self, len: u32) -> Result<Vec<Cluster>, FsError> {
+        let mut clusters: Vec<Cluster> = if self.entry.start_cluster.0 == 0 {
+            Vec::new()
+        } else {
+            self.fs.chain(self.entry.start_cluster).collect::<Result<_, _>>()?
+        };
+        let needed = (len as u64).div_ceil(self.fs.cluster_size as u64) as usize;
+        if clusters.len() >= needed {
+            return Ok(clusters);
+        }
+
+        let count = (needed - clusters.len()) as u32;
+        let added = self.fs.allocate_clusters(count, clusters.last().copied())?;
+        for pair in added.windows(2) {
+            FatValue::put(self.fs, pair[0], FatValue::Data(pair[1].0))?;
+        }
+
+        match clusters.last() {
+            Some(&last) => FatValue::put(self.fs, last, FatValue::Data(added[0].0))?,
+            // An empty file only points at its chain once the entry is updated.
+            None => self.entry.start_cluster = added[0],
+        }
+        clusters.extend(added);
+        Ok(clusters)
+    }
+
+    // Write zeros from byte `start` up to `end`, one cluster at a time so
+    // that a large gap needs no buffer of its size.
+    fn zero_range(&self, clusters: &[Cluster], start: u32, end: u32) -> Result<(), FsError> {
+        if start >= end {
+            return Ok(());
+        }
+        let zeros = vec![0u8; self.fs.cluster_size as usize];
+        let mut position = start;
+        while position < end {
+            // Align to cluster boundaries after the first piece.
+            let within = position % self.fs.cluster_size;
+            let count = (self.fs.cluster_size - within).min(end - position);
+            self.write_range(clusters, position, &zeros[..count as usize])?;
+            position += count;
+        }
+        Ok(())
+    }
+
+    // Write `data` at byte `position` of the file laid out on `clusters`.
+    fn write_range(&self, clusters: &[Cluster], position: u32, data: &[u8]) -> Result<(), FsError> {
+        let cluster_size = self.fs.cluster_size as usize;
+        let mut done = 0;
+        while done < data.len() {
+            let position = position as usize + done;
+            let within = position % cluster_size;
+            let count = (cluster_size - within).min(data.len() - done);
+            let cluster = clusters[position / cluster_size];
+            self.fs.write_cluster_at(cluster, within as u32, &data[done..done + count])?;
+            done += count;
+        }
+        Ok(())
+    }
+}
+
+impl<S: StorageDevice> Drop for File<'_, S> {
+    fn drop(&mut self) {
+        self.fs.open_files.release(self.entry_offset, self.mode);
+    }
+}
@@ -1,36 +1,482 @@
 //! Simple FAT32 Filesystem Implementation
 
+use crate::boot_sector::{BootSector, FsInfo, FS_INFO_UNKNOWN};
+use crate::directory::attribute::Attributes;
 use crate::directory::cluster::Cluster;
-use crate::directory::table::FatValue;
-use spin::Mutex;
+use crate::directory::name::ILLEGAL_NAME_CHARS;
+use crate::directory::dir_entry::{
+    DirectoryEntry, DirectoryIterator, DirectorySlots, DELETED_ENTRY, ENTRY_SIZE,
+};
+use crate::directory::offset_iter::ClusterChain;
+use crate::directory::table::{
+    FatValue, CLEAN_SHUTDOWN, FAT16_CLEAN_SHUTDOWN, FAT16_NO_HARD_ERROR, NO_HARD_ERROR,
+};
+use crate::error::FsError;
+use crate::file::File;
+use crate::journal::{Journal, JOURNAL_FILE};
+use crate::lock::{DirectoryGuard, DirectoryLocks, OpenFiles, OpenMode};
+use crate::volume::FatType;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::{Mutex, MutexGuard, RwLock};
+
+/// Highest number of data clusters a FAT32 volume can have.
+pub const MAX_CLUSTERS: u32 = 0x0FFF_FFF5;
 
 pub trait StorageDevice {
-    fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<(), ()>;
-    fn write(&self, offset: u64, buffer: &[u8]) -> Result<(), ()>;
+    fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<(), FsError>;
+    fn write(&self, offset: u64, buffer: &[u8]) -> Result<(), FsError>;
+
+    /// Size of the device in bytes, if known.
+    fn size(&self) -> Option<u64> {
+        None
+    }
+
+    /// Flush buffered writes to the underlying medium.
+    fn flush(&self) -> Result<(), FsError> {
+        Ok(())
+    }
+
+    /// Discard a byte range so that it reads back as zeros, e.g. with TRIM.
+    /// Returns `false` if the device cannot, leaving the range untouched.
+    fn discard(&self, _offset: u64, _len: u64) -> Result<bool, FsError> {
+        Ok(false)
+    }
+}
+
+// Borrowed devices, e.g. a partition view over a disk that stays in use.
+impl<S: StorageDevice + ?Sized> StorageDevice for &S {
+    fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<(), FsError> {
+        (**self).read(offset, buffer)
+    }
+
+    fn write(&self, offset: u64, buffer: &[u8]) -> Result<(), FsError> {
+        (**self).write(offset, buffer)
+    }
+
+    fn size(&self) -> Option<u64> {
+        (**self).size()
+    }
+
+    fn flush(&self) -> Result<(), FsError> {
+        (**self).flush()
+    }
+
+    fn discard(&self, offset: u64, len: u64) -> Result<bool, FsError> {
+        (**self).discard(offset, len)
+    }
+}
+
+/// Options for `FatFileSystem::mount_with`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MountOptions {
+    pub refuse_dirty: bool, // Fail with `DirtyVolume` if not cleanly unmounted or a hard error is recorded.
+    pub read_only: bool,    // Reject every write; the device is never written.
+}
+
+/// Options for `FatFileSystem::remove_with`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RemoveOptions {
+    pub erase: bool, // Erase the clusters and clear the slot so the data cannot be recovered.
+}
+
+/// Geometry of a volume, derived from its boot sector.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Layout {
+    pub cluster_size: u32,
+    pub fat_start: u64,
+    pub fat_size: u64,
+    pub num_fats: u8,
+    pub data_start: u64,
+    pub cluster_count: u32,
+    pub fat_type: FatType,
+    pub root_cluster: Cluster,
+    pub root_dir_start: u64,
+    pub root_dir_size: u64,
+    pub fs_info_offset: Option<u64>,
 }
 
+impl Layout {
+    /// Check the boot sector describes a usable FAT volume and locate its regions.
+    pub(crate) fn from_boot(boot: &BootSector) -> Result<Self, FsError> {
+        // The FAT type follows from the cluster count alone.
+        let cluster_count = boot.cluster_count();
+        let fat_type = FatType::from_cluster_count(cluster_count);
+        let sector_size = boot.bytes_per_sector as u64;
+        let fat_size = boot.fat_size() as u64 * sector_size;
+        let entry_bits = match fat_type {
+            FatType::Fat32 => 32,
+            other => other.entry_bits() as u64,
+        };
+        if cluster_count > MAX_CLUSTERS
+            || fat_size * 8 < (cluster_count as u64 + 2) * entry_bits
+        {
+            return Err(FsError::InvalidBootSector);
+        }
+
+        // FAT12/16 keep the root directory in a fixed region before the data.
+        let (root_cluster, fs_info_offset) = match fat_type {
+            FatType::Fat32 => {
+                let root_cluster = Cluster(boot.root_cluster);
+                let root_valid = root_cluster.0 >= 2 && root_cluster.0 < cluster_count + 2;
+                if boot.fat_size_16 != 0 || boot.root_entry_count != 0 || !root_valid {
+                    return Err(FsError::InvalidBootSector);
+                }
+                let fs_info_offset = match boot.fs_info_sector {
+                    0 | 0xFFFF => None,
+                    sector => Some(sector as u64 * sector_size),
+                };
+                (root_cluster, fs_info_offset)
+            }
+            FatType::Fat16 | FatType::Fat12 => {
+                if boot.root_entry_count == 0 {
+                    return Err(FsError::InvalidBootSector);
+                }
+                (Cluster(0), None)
+            }
+        };
+        let fat_start = boot.reserved_sectors as u64 * sector_size;
+        let root_dir_start = fat_start + boot.num_fats as u64 * fat_size;
+
+        Ok(Self {
+            cluster_size: boot.cluster_size(),
+            fat_start,
+            fat_size,
+            num_fats: boot.num_fats,
+            data_start: boot.first_data_sector() as u64 * sector_size,
+            cluster_count,
+            fat_type,
+            root_cluster,
+            root_dir_start,
+            root_dir_size: boot.root_dir_sectors() as u64 * sector_size,
+            fs_info_offset,
+        })
+    }
+}
+
+/// A mounted FAT volume, which threads can share; see `crate::lock`.
 pub struct FatFileSystem<S: StorageDevice> {
-    pub storage_device: Mutex<S>,
+    pub storage_device: S,            // Not locked: devices synchronize their own accesses.
     pub partition_start: u64,
     pub cluster_size: u32,
+    pub fat_start: u64,               // Offset of the first FAT, relative to the partition.
+    pub fat_size: u64,                // Bytes per FAT copy.
+    pub num_fats: u8,                 // Number of mirrored FAT copies.
+    pub data_start: u64,              // Offset of cluster 2, relative to the partition.
+    pub cluster_count: u32,           // Number of data clusters.
+    pub fat_type: FatType,            // Entry width, from the cluster count.
+    pub root_cluster: Cluster,        // First cluster of the root directory, 0 if fixed.
+    pub root_dir_start: u64,          // Offset of the fixed FAT12/16 root directory.
+    pub root_dir_size: u64,           // Bytes of the fixed root directory, 0 on FAT32.
+    pub fs_info_offset: Option<u64>,  // Offset of the FSInfo sector, if any.
+    pub was_dirty: bool,              // The volume was not cleanly unmounted last time.
+    pub had_errors: bool,             // The volume recorded a hard I/O error.
+    fs_info: Mutex<Option<FsInfo>>,   // Free cluster hints, written back by `sync`.
+    tracks_dirty: bool,               // The dirty bit is set while mounted.
+    write_failed: AtomicBool,         // A device write failed; cleared by a fsck repair.
+    read_only: bool,                  // Mutating operations fail with `ReadOnly`.
+    journal: RwLock<Option<Journal>>, // Metadata journal, if the volume has one.
+    allocation: Mutex<()>,            // Held while free clusters are found and claimed.
+    pub(crate) fat_update: Mutex<()>, // Held across read-modify-writes of FAT entries.
+    pub(crate) open_files: OpenFiles, // Sharing modes of the open files.
+    directories: DirectoryLocks,      // Directories being changed.
+    moves: Mutex<()>,                 // Held by renames across directories.
+    transactions: Mutex<()>,          // Held by the journal transaction in progress.
 }
 
 impl<S: StorageDevice> FatFileSystem<S> {
     /// Create a new FAT32 filesystem instance.
+    ///
+    /// No boot sector is read: the FAT and the data region both start at
+    /// `partition_start`. Use `mount` for real volumes.
     pub fn new(storage_device: S, partition_start: u64, cluster_size: u32) -> Self {
+        let cluster_count = MAX_CLUSTERS;
         Self {
-            storage_device: Mutex::new(storage_device),
+            storage_device,
             partition_start,
             cluster_size,
+            fat_start: 0,
+            fat_size: (cluster_count as u64 + 2) * 4,
+            num_fats: 1,
+            data_start: 0,
+            cluster_count,
+            fat_type: FatType::Fat32,
+            root_cluster: Cluster(2),
+            root_dir_start: 0,
+            root_dir_size: 0,
+            fs_info_offset: None,
+            was_dirty: false,
+            had_errors: false,
+            fs_info: Mutex::new(None),
+            tracks_dirty: false,
+            write_failed: AtomicBool::new(false),
+            read_only: false,
+            journal: RwLock::new(None),
+            allocation: Mutex::new(()),
+            fat_update: Mutex::new(()),
+            open_files: OpenFiles::new(),
+            directories: DirectoryLocks::new(),
+            moves: Mutex::new(()),
+            transactions: Mutex::new(()),
         }
     }
 
+    /// Mount a FAT volume starting at offset zero of `storage_device`.
+    pub fn mount(storage_device: S) -> Result<Self, FsError> {
+        Self::mount_at(storage_device, 0)
+    }
+
+    /// Mount a FAT volume whose boot sector is at `partition_start`.
+    pub fn mount_at(storage_device: S, partition_start: u64) -> Result<Self, FsError> {
+        Self::mount_with(storage_device, partition_start, MountOptions::default())
+    }
+
+    /// Mount a FAT12, FAT16 or FAT32 volume with explicit options.
+    ///
+    /// The volume is flagged dirty in FAT entry 1 until `unmount` is called.
+    pub fn mount_with(
+        storage_device: S,
+        partition_start: u64,
+        options: MountOptions,
+    ) -> Result<Self, FsError> {
+        let mut sector = [0u8; 512];
+        storage_device.read(partition_start, &mut sector)?;
+        let layout = Layout::from_boot(&BootSector::parse(&sector)?)?;
+
+        let mut fs = Self {
+            storage_device,
+            partition_start,
+            cluster_size: layout.cluster_size,
+            fat_start: layout.fat_start,
+            fat_size: layout.fat_size,
+            num_fats: layout.num_fats,
+            data_start: layout.data_start,
+            cluster_count: layout.cluster_count,
+            fat_type: layout.fat_type,
+            root_cluster: layout.root_cluster,
+            root_dir_start: layout.root_dir_start,
+            root_dir_size: layout.root_dir_size,
+            fs_info_offset: layout.fs_info_offset,
+            was_dirty: false,
+            had_errors: false,
+            fs_info: Mutex::new(None),
+            tracks_dirty: !options.read_only,
+            write_failed: AtomicBool::new(false),
+            read_only: options.read_only,
+            journal: RwLock::new(None),
+            allocation: Mutex::new(()),
+            fat_update: Mutex::new(()),
+            open_files: OpenFiles::new(),
+            directories: DirectoryLocks::new(),
+            moves: Mutex::new(()),
+            transactions: Mutex::new(()),
+        };
+
+        if let Some((clean, no_error)) = fs.dirty_flags() {
+            let flags = FatValue::get_raw(&fs, Cluster(1))?;
+            fs.was_dirty = flags & clean == 0;
+            fs.had_errors = flags & no_error == 0;
+        }
+        if options.refuse_dirty && (fs.was_dirty || fs.had_errors) {
+            return Err(FsError::DirtyVolume);
+        }
+
+        fs.attach_journal()?;
+
+        // A damaged FSInfo sector only loses the hints.
+        if let Some(offset) = fs.fs_info_offset {
+            let mut sector = [0u8; 512];
+            fs.read_at(offset, &mut sector)?;
+            *fs.fs_info.lock() = FsInfo::parse(&sector).ok();
+        }
+
+        fs.set_clean(false)?;
+        Ok(fs)
+    }
+
+    /// Check if the volume was mounted read-only.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Write cached metadata back and flush the device, leaving the volume mounted.
+    pub fn sync(&self) -> Result<(), FsError> {
+        if self.read_only {
+            return Ok(());
+        }
+        if let (Some(offset), Some(fs_info)) = (self.fs_info_offset, *self.fs_info.lock()) {
+            self.write_at(offset, &fs_info.to_bytes())?;
+        }
+        if self.write_failed.load(Ordering::Relaxed) {
+            self.set_clean(false)?;
+        }
+        self.storage_device.flush()
+    }
+
+    /// Flush everything, mark the volume clean and release the device.
+    pub fn unmount(self) -> Result<S, FsError> {
+        if !self.read_only {
+            self.sync()?;
+            self.set_clean(true)?;
+            self.storage_device.flush()?;
+        }
+        Ok(self.storage_device)
+    }
+
+    // Clean-shutdown and no-error bits of FAT entry 1, if the FAT type has them.
+    fn dirty_flags(&self) -> Option<(u32, u32)> {
+        dirty_flags(self.fat_type)
+    }
+
+    // Set or clear the clean-shutdown bit of FAT entry 1, recording a failed
+    // write in the no-error bit.
+    fn set_clean(&self, clean: bool) -> Result<(), FsError> {
+        let Some((bit, no_error)) = self.dirty_flags().filter(|_| self.tracks_dirty) else {
+            return Ok(());
+        };
+        let flags = FatValue::get_raw(self, Cluster(1))?;
+        let mut flags = if clean { flags | bit } else { flags & !bit };
+        if self.write_failed.load(Ordering::Relaxed) {
+            flags &= !no_error;
+        }
+        FatValue::put_raw(self, Cluster(1), flags)?;
+        self.storage_device.flush()
+    }
+
+    // Clear the hard error recorded in FAT entry 1, once fsck repaired the volume.
+    pub(crate) fn clear_hard_error(&self) -> Result<(), FsError> {
+        self.write_failed.store(false, Ordering::Relaxed);
+        let Some((_, no_error)) = self.dirty_flags().filter(|_| self.tracks_dirty) else {
+            return Ok(());
+        };
+        let flags = FatValue::get_raw(self, Cluster(1))?;
+        FatValue::put_raw(self, Cluster(1), flags | no_error)?;
+        self.storage_device.flush()
+    }
+
+    /// Flush barrier between dependent metadata updates.
+    pub fn barrier(&self) -> Result<(), FsError> {
+        if let Some(journal) = self.journal.write().as_mut() {
+            journal.checkpoint(self)?;
+        }
+        self.storage_device.flush()
+    }
+
+    /// Check if metadata updates go through a journal.
+    pub fn has_journal(&self) -> bool {
+        self.journal.read().is_some()
+    }
+
+    /// Start journaling if the root directory holds a journal file, replaying
+    /// a transaction interrupted by a crash. Read-only mounts keep the replayed
+    /// blocks in memory instead. A sealed transaction on a cleanly unmounted
+    /// volume predates changes made since, maybe without the journal, and is
+    /// discarded; FAT12 records no clean shutdown and always replays.
+    pub(crate) fn attach_journal(&self) -> Result<(), FsError> {
+        let entry = match self.find_entry(self.root_cluster, JOURNAL_FILE) {
+            Ok((entry, _)) if entry.is_file() && entry.start_cluster.0 != 0 => entry,
+            Ok(_) | Err(FsError::NotFound) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let length = entry.file_size.div_ceil(self.cluster_size) as usize;
+        let clusters = self.chain(entry.start_cluster).take(length).collect::<Result<Vec<_>, _>>()?;
+        let mut journal = Journal::load(self, &clusters)?;
+        if !self.was_dirty && self.dirty_flags().is_some() {
+            journal.discard(self)?;
+        } else if !self.read_only {
+            journal.replay(self)?;
+        }
+        *self.journal.write() = Some(journal);
+        Ok(())
+    }
+
+    /// Run `operation` as one journal transaction, which commits even if
+    /// `operation` failed so that its cleanup is kept.
+    ///
+    /// Transactions run one at a time, so each commits only its own writes
+    /// and gets its own commit errors. They must not nest, and `operation`
+    /// must not wait for a directory lock.
+    pub(crate) fn transaction<T>(
+        &self,
+        operation: impl FnOnce() -> Result<T, FsError>,
+    ) -> Result<T, FsError> {
+        if self.read_only || !self.has_journal() {
+            return operation();
+        }
+        let _turn = self.transactions.lock();
+        self.journal.write().as_mut().unwrap().begin();
+        let result = operation();
+        let mut journal = self.journal.write();
+        let journal = journal.as_mut().unwrap();
+        journal.end();
+        let committed = journal.commit(self);
+        result.and_then(|value| committed.map(|_| value))
+    }
+
+    /// Read bytes at an offset relative to the partition start.
+    pub fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<(), FsError> {
+        // Holding the journal keeps a commit from landing between the two steps.
+        let journal = self.journal.read();
+        self.device_read(offset, buffer)?;
+        if let Some(journal) = journal.as_ref() {
+            journal.overlay(offset, buffer);
+        }
+        Ok(())
+    }
+
+    /// Write bytes at an offset relative to the partition start.
+    /// Inside a journal transaction the write is staged until commit.
+    pub fn write_at(&self, offset: u64, data: &[u8]) -> Result<(), FsError> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
+        }
+        if let Some(journal) = self.journal.write().as_mut() {
+            if journal.in_transaction() {
+                return journal.stage(self, offset, data);
+            }
+        }
+        self.device_write(offset, data)
+    }
+
+    // Raw partition-relative device access, bypassing the journal.
+    pub(crate) fn device_read(&self, offset: u64, buffer: &mut [u8]) -> Result<(), FsError> {
+        self.storage_device.read(self.partition_start + offset, buffer)
+    }
+
+    // A failed write is recorded as a hard error by the next `sync` or `unmount`.
+    pub(crate) fn device_flush(&self) -> Result<(), FsError> {
+        self.storage_device.flush()
+    }
+
+    pub(crate) fn device_write(&self, offset: u64, data: &[u8]) -> Result<(), FsError> {
+        let result = self.storage_device.write(self.partition_start + offset, data);
+        if result == Err(FsError::Io) {
+            self.write_failed.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Offset of a data cluster, relative to the partition start.
+    pub fn cluster_offset(&self, cluster: Cluster) -> u64 {
+        self.data_start + cluster.to_offset(self.cluster_size)
+    }
+
+    /// Check if `dir` designates the fixed FAT12/16 root directory.
+    pub fn is_fixed_root(&self, dir: Cluster) -> bool {
+        self.root_dir_size > 0 && dir.0 == 0
+    }
+
+    /// Check if a cluster number lies inside the data region.
+    pub fn is_data_cluster(&self, cluster: Cluster) -> bool {
+        cluster.0 >= 2 && cluster.0 < self.cluster_count + 2
+    }
+
     /// Read a cluster from the filesystem.
     pub fn read_cluster(&self, cluster: Cluster) -> Option<alloc::vec::Vec<u8>> {
         let mut buffer = alloc::vec![0; self.cluster_size as usize];
-        let offset = self.partition_start + cluster.to_offset(self.cluster_size);
+        let offset = self.cluster_offset(cluster);
 
-        if self.storage_device.lock().read(offset, &mut buffer).is_ok() {
+        if self.read_at(offset, &mut buffer).is_ok() {
             Some(buffer)
         } else {
             None
@@ -38,25 +484,643 @@ impl<S: StorageDevice> FatFileSystem<S> {
     }
 
     /// Write data to a cluster.
-    pub fn write_cluster(&self, cluster: Cluster, data: &[u8]) -> bool {
-        let offset = self.partition_start + cluster.to_offset(self.cluster_size);
-        self.storage_device.lock().write(offset, data).is_ok()
+    pub fn write_cluster(&self, cluster: Cluster, data: &[u8]) -> Result<(), FsError> {
+        self.write_cluster_at(cluster, 0, data)
+    }
+
+    /// Write data at byte `within` of a cluster.
+    ///
+    /// Cluster contents are never journaled: they must only become reachable
+    /// through a later metadata update.
+    pub fn write_cluster_at(
+        &self,
+        cluster: Cluster,
+        within: u32,
+        data: &[u8],
+    ) -> Result<(), FsError> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
+        }
+        let offset = self.cluster_offset(cluster) + within as u64;
+        self.device_write(offset, data)?;
+        if let Some(journal) = self.journal.write().as_mut() {
+            journal.patch(offset, data);
+        }
+        Ok(())
+    }
+
+    /// Make a byte range read as zeros, discarding it if the device can.
+    /// Returns whether it was discarded. Like cluster writes, this bypasses
+    /// the journal.
+    pub(crate) fn erase(&self, offset: u64, len: u64) -> Result<bool, FsError> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
+        }
+        const CHUNK: u64 = 64 * 1024;
+        let discarded = self.storage_device.discard(self.partition_start + offset, len)?;
+        let zeros = vec![0u8; len.min(CHUNK) as usize];
+        let mut done = 0;
+        while done < len {
+            let count = (len - done).min(CHUNK) as usize;
+            if !discarded {
+                self.device_write(offset + done, &zeros[..count])?;
+            }
+            if let Some(journal) = self.journal.write().as_mut() {
+                journal.patch(offset + done, &zeros[..count]);
+            }
+            done += count as u64;
+        }
+        Ok(discarded)
     }
 
     /// Allocate a new cluster.
-    pub fn allocate_cluster(&self) -> Option<Cluster> {
-        for cluster_id in 2..0xFFFF_FFF8 {
-            if let FatValue::Free = FatValue::get(self, Cluster(cluster_id)) {
-                FatValue::put(self, Cluster(cluster_id), FatValue::EndOfChain);
-                return Some(Cluster(cluster_id));
+    pub fn allocate_cluster(&self) -> Result<Cluster, FsError> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
+        }
+        let _allocation = self.allocation.lock();
+        self.allocate_locked()
+    }
+
+    // Allocate a cluster with the allocation lock held.
+    fn allocate_locked(&self) -> Result<Cluster, FsError> {
+        // Start at the FSInfo hint and wrap around.
+        let end = self.cluster_count + 2;
+        let hint = match self.fs_info() {
+            Some(FsInfo { next_free, .. }) if (2..end).contains(&next_free) => next_free,
+            _ => 2,
+        };
+
+        for cluster_id in (hint..end).chain(2..hint) {
+            if let FatValue::Free = FatValue::get(self, Cluster(cluster_id))? {
+                FatValue::put(self, Cluster(cluster_id), FatValue::EndOfChain)?;
+                self.update_fs_info(|info| {
+                    if info.free_count != FS_INFO_UNKNOWN {
+                        info.free_count = info.free_count.saturating_sub(1);
+                    }
+                    info.next_free = cluster_id + 1;
+                });
+                return Ok(Cluster(cluster_id));
             }
         }
-        None
+        Err(FsError::NoSpace)
+    }
+
+    /// Allocate `count` clusters, each marked as the end of a chain, preferring
+    /// a contiguous run: first right after `after`, then the first run found
+    /// from the FSInfo hint. Falls back to scattered clusters if no run fits.
+    pub fn allocate_clusters(
+        &self,
+        count: u32,
+        after: Option<Cluster>,
+    ) -> Result<Vec<Cluster>, FsError> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
+        }
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let _allocation = self.allocation.lock();
+
+        let end = self.cluster_count + 2;
+        let is_free = |id: u32| Ok(FatValue::get(self, Cluster(id))? == FatValue::Free);
+        let all_free = |first: u32| (first..first + count).try_fold(true, |all, id| Ok(all && is_free(id)?));
+        let mut run = None;
+        if let Some(first) = after
+            .map(|cluster| cluster.0 + 1)
+            .filter(|&first| first >= 2 && first as u64 + count as u64 <= end as u64)
+        {
+            if all_free(first)? {
+                run = Some(first);
+            }
+        }
+        if run.is_none() {
+            let hint = match self.fs_info() {
+                Some(FsInfo { next_free, .. }) if (2..end).contains(&next_free) => next_free,
+                _ => 2,
+            };
+            let (mut start, mut len) = (hint, 0);
+            for id in (hint..end).chain(2..hint) {
+                // A run cannot wrap past the end, so restart it there.
+                if id == 2 {
+                    len = 0;
+                }
+                if !is_free(id)? {
+                    len = 0;
+                    continue;
+                }
+                if len == 0 {
+                    start = id;
+                }
+                len += 1;
+                if len == count {
+                    run = Some(start);
+                    break;
+                }
+            }
+        }
+
+        let Some(first) = run else {
+            let mut clusters = Vec::with_capacity(count as usize);
+            while clusters.len() < count as usize {
+                match self.allocate_locked() {
+                    Ok(cluster) => clusters.push(cluster),
+                    Err(err) => {
+                        for &cluster in &clusters {
+                            self.free_cluster(cluster)?;
+                        }
+                        return Err(err);
+                    }
+                }
+            }
+            return Ok(clusters);
+        };
+
+        let clusters: Vec<Cluster> = (first..first + count).map(Cluster).collect();
+        self.claim_clusters(&clusters)?;
+        self.update_fs_info(|info| info.next_free = first + count);
+        Ok(clusters)
+    }
+
+    /// Hold off allocations, e.g. while checking clusters are free before
+    /// `claim_clusters` takes them.
+    pub(crate) fn lock_allocation(&self) -> MutexGuard<'_, ()> {
+        self.allocation.lock()
+    }
+
+    /// Mark specific free clusters as allocated, each as the end of a chain.
+    /// The caller holds `lock_allocation` from the check that they are free.
+    pub(crate) fn claim_clusters(&self, clusters: &[Cluster]) -> Result<(), FsError> {
+        for &cluster in clusters {
+            FatValue::put(self, cluster, FatValue::EndOfChain)?;
+        }
+        self.update_fs_info(|info| {
+            if info.free_count != FS_INFO_UNKNOWN {
+                info.free_count = info.free_count.saturating_sub(clusters.len() as u32);
+            }
+        });
+        Ok(())
     }
 
     /// Free a cluster.
-    pub fn free_cluster(&self, cluster: Cluster) {
-        FatValue::put(self, cluster, FatValue::Free);
+    pub fn free_cluster(&self, cluster: Cluster) -> Result<(), FsError> {
+        FatValue::put(self, cluster, FatValue::Free)?;
+        self.update_fs_info(|info| {
+            if info.free_count != FS_INFO_UNKNOWN {
+                info.free_count = (info.free_count + 1).min(self.cluster_count);
+            }
+        });
+        Ok(())
+    }
+
+    /// Free every cluster of the chain starting at `start`.
+    pub fn free_chain(&self, start: Cluster) -> Result<(), FsError> {
+        if start.0 == 0 {
+            return Ok(());
+        }
+        let clusters = self.chain(start).collect::<Result<Vec<_>, _>>()?;
+        for cluster in clusters {
+            self.free_cluster(cluster)?;
+        }
+        Ok(())
+    }
+
+    /// Cached FSInfo hints, if the volume has a valid FSInfo sector.
+    pub fn fs_info(&self) -> Option<FsInfo> {
+        *self.fs_info.lock()
+    }
+
+    /// Replace the cached FSInfo hints; they are written back by `sync`.
+    pub fn set_fs_info(&self, fs_info: FsInfo) {
+        if self.fs_info_offset.is_some() {
+            *self.fs_info.lock() = Some(fs_info);
+        }
+    }
+
+    fn update_fs_info(&self, update: impl FnOnce(&mut FsInfo)) {
+        if let Some(info) = self.fs_info.lock().as_mut() {
+            update(info);
+        }
+    }
+
+    /// Follow the cluster chain starting at `start`.
+    pub fn chain(&self, start: Cluster) -> ClusterChain<'_, S> {
+        ClusterChain::new(self, start)
+    }
+
+    // Fail with `AlreadyExists` if `name` is taken in directory `dir`. A
+    // failed lookup is passed on rather than taken as a free name.
+    pub(crate) fn check_absent(&self, dir: Cluster, name: &str) -> Result<(), FsError> {
+        match self.find_entry(dir, name) {
+            Ok(_) => Err(FsError::AlreadyExists),
+            Err(FsError::NotFound) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Look up an entry by its 8.3 name in directory `dir`.
+    /// Returns the entry and the offset of its slot.
+    pub fn find_entry(&self, dir: Cluster, name: &str) -> Result<(DirectoryEntry, u64), FsError> {
+        for slot in DirectorySlots::new(self, dir) {
+            let slot = slot?;
+            if slot.is_end() {
+                break;
+            }
+            if slot.is_deleted() || slot.is_long_name() {
+                continue;
+            }
+            let entry = slot.entry();
+            if !entry.is_volume_label() && entry.file_name().eq_ignore_ascii_case(name) {
+                return Ok((entry, slot.offset));
+            }
+        }
+        Err(FsError::NotFound)
+    }
+
+    /// Resolve a `/`-separated path from the root directory.
+    /// The root itself resolves to its "." entry.
+    pub fn lookup(&self, path: &str) -> Result<DirectoryEntry, FsError> {
+        let mut entry = DirectoryEntry::dot(self.root_cluster);
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if !entry.is_directory() {
+                return Err(FsError::NotFound);
+            }
+            (entry, _) = self.find_entry(self.directory_cluster(&entry), name)?;
+        }
+        Ok(entry)
+    }
+
+    /// Resolve the directory holding the last component of `path`.
+    /// Returns the directory's cluster and the component.
+    pub fn lookup_parent<'p>(&self, path: &'p str) -> Result<(Cluster, &'p str), FsError> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        if name.is_empty() {
+            return Err(FsError::InvalidArgument); // The root has no parent.
+        }
+        let parent = self.lookup(parent)?;
+        if !parent.is_directory() {
+            return Err(FsError::NotFound);
+        }
+        Ok((self.directory_cluster(&parent), name))
+    }
+
+    /// First cluster of a directory, mapping ".." entries of top-level
+    /// directories (cluster 0) to the root.
+    pub fn directory_cluster(&self, entry: &DirectoryEntry) -> Cluster {
+        match entry.start_cluster {
+            Cluster(0) => self.root_cluster,
+            cluster => cluster,
+        }
+    }
+
+    /// Rewrite the name, attributes, start cluster and size of the entry at `offset`,
+    /// keeping its timestamps.
+    pub fn update_entry(&self, offset: u64, entry: &DirectoryEntry) -> Result<(), FsError> {
+        let mut bytes = [0u8; ENTRY_SIZE];
+        self.read_at(offset, &mut bytes)?;
+        entry.write_into(&mut bytes);
+        self.write_at(offset, &bytes)
+    }
+
+    /// Replace the read-only, hidden, system and archive flags of the entry at `path`.
+    /// The directory and volume label flags describe the entry and cannot be set.
+    /// Fails with `Busy` while the file is open for writing.
+    pub fn set_attributes(&self, path: &str, attributes: Attributes) -> Result<(), FsError> {
+        if !Attributes::USER.contains(attributes) {
+            return Err(FsError::InvalidArgument);
+        }
+        let (dir, name) = self.lookup_parent(path)?;
+        let _locked = self.lock_directories(&[dir]);
+        let (mut entry, offset) = self.find_entry(dir, name)?;
+        if entry.is_dot() {
+            return Err(FsError::InvalidArgument);
+        }
+        if self.is_attached_journal(dir, &entry) {
+            return Err(FsError::AccessDenied);
+        }
+        // A writer would put its own copy of the attributes back.
+        if self.open_files.is_open_for_writing(offset) {
+            return Err(FsError::Busy);
+        }
+        entry.attributes = entry.attributes.difference(Attributes::USER) | attributes;
+        self.transaction(|| self.update_entry(offset, &entry))?;
+        self.barrier()
+    }
+
+    /// Store `entry` in the first free slot of directory `dir`, growing it if full.
+    /// Returns the offset of the slot used.
+    pub fn add_entry(&self, dir: Cluster, entry: &DirectoryEntry) -> Result<u64, FsError> {
+        let _locked = self.lock_directories(&[dir]);
+        self.transaction(|| self.add_entry_inner(dir, entry))
+    }
+
+    pub(crate) fn add_entry_inner(&self, dir: Cluster, entry: &DirectoryEntry) -> Result<u64, FsError> {
+        let mut last = dir;
+        for slot in DirectorySlots::new(self, dir) {
+            let slot = slot?;
+            if slot.is_end() || slot.is_deleted() {
+                self.write_at(slot.offset, &entry.to_bytes())?;
+                return Ok(slot.offset);
+            }
+            if self.is_fixed_root(dir) {
+                continue;
+            }
+            last = self.cluster_of(slot.offset);
+        }
+        if self.is_fixed_root(dir) {
+            return Err(FsError::NoSpace); // The fixed root directory cannot grow.
+        }
+
+        // Zero the new cluster before linking it so the directory never ends in garbage.
+        let cluster = self.allocate_cluster()?;
+        if let Err(err) = self.write_cluster(cluster, &vec![0; self.cluster_size as usize]) {
+            self.free_cluster(cluster)?;
+            return Err(err);
+        }
+        FatValue::put(self, last, FatValue::Data(cluster.0))?;
+
+        let offset = self.cluster_offset(cluster);
+        self.write_at(offset, &entry.to_bytes())?;
+        Ok(offset)
+    }
+
+    /// Create an empty subdirectory `name` in directory `parent`.
+    pub fn create_directory(&self, parent: Cluster, name: &str) -> Result<DirectoryEntry, FsError> {
+        check_name(name)?;
+        let _locked = self.lock_directories(&[parent]);
+        self.transaction(|| self.create_directory_inner(parent, name))
+    }
+
+    fn create_directory_inner(
+        &self,
+        parent: Cluster,
+        name: &str,
+    ) -> Result<DirectoryEntry, FsError> {
+        self.check_absent(parent, name)?;
+
+        let cluster = self.allocate_cluster()?;
+        // ".." points to cluster 0 when the parent is the root directory.
+        let parent_link = if parent == self.root_cluster { Cluster(0) } else { parent };
+        let mut data = vec![0u8; self.cluster_size as usize];
+        data[..ENTRY_SIZE].copy_from_slice(&DirectoryEntry::dot(cluster).to_bytes());
+        let dot_dot = DirectoryEntry::dot_dot(parent_link);
+        data[ENTRY_SIZE..2 * ENTRY_SIZE].copy_from_slice(&dot_dot.to_bytes());
+        if let Err(err) = self.write_cluster(cluster, &data) {
+            self.free_cluster(cluster)?;
+            return Err(err);
+        }
+
+        let entry = DirectoryEntry::new(name, cluster, 0, Attributes::DIRECTORY);
+        if let Err(err) = self.add_entry_inner(parent, &entry) {
+            self.free_cluster(cluster)?;
+            return Err(err);
+        }
+        Ok(entry)
+    }
+
+    /// Create an empty file `name` in directory `dir` and open it for writing.
+    pub fn create_file(&self, dir: Cluster, name: &str) -> Result<File<'_, S>, FsError> {
+        check_name(name)?;
+        let _locked = self.lock_directories(&[dir]);
+        self.check_absent(dir, name)?;
+        // No clusters yet, so the entry alone makes the file exist.
+        let entry = DirectoryEntry::new(name, Cluster(0), 0, Attributes::ARCHIVE);
+        let offset = self.transaction(|| self.add_entry_inner(dir, &entry))?;
+        self.barrier()?;
+        File::open(self, entry, offset, OpenMode::Write)
+    }
+
+    /// Open the file `name` in directory `dir` for writing.
+    pub fn open_file(&self, dir: Cluster, name: &str) -> Result<File<'_, S>, FsError> {
+        self.open_file_with(dir, name, OpenMode::Write)
+    }
+
+    /// Open the file `name` in directory `dir` in the given mode.
+    ///
+    /// Any number of readers can share a file, but a writer needs it to
+    /// itself: a conflicting open fails with `Busy`.
+    pub fn open_file_with(
+        &self,
+        dir: Cluster,
+        name: &str,
+        mode: OpenMode,
+    ) -> Result<File<'_, S>, FsError> {
+        // Keeps the entry from being removed before the handle registers.
+        let _locked = self.lock_directories(&[dir]);
+        let (entry, offset) = self.find_entry(dir, name)?;
+        if entry.is_directory() {
+            return Err(FsError::IsDirectory);
+        }
+        if mode == OpenMode::Write && self.is_attached_journal(dir, &entry) {
+            return Err(FsError::AccessDenied);
+        }
+        File::open(self, entry, offset, mode)
+    }
+
+    /// Delete the file or empty directory `name` from directory `dir`.
+    /// Read-only entries and the journal in use are refused with `AccessDenied`,
+    /// open files with `Busy`.
+    ///
+    /// The entry is removed before its chain is freed, so a crash in between
+    /// only leaves a lost chain.
+    pub fn remove(&self, dir: Cluster, name: &str) -> Result<(), FsError> {
+        self.remove_with(dir, name, RemoveOptions::default())
+    }
+
+    /// Delete `name` from directory `dir` with explicit options.
+    ///
+    /// When erasing, the data goes first, so a crash leaves at worst an
+    /// entry whose contents read as zeros.
+    pub fn remove_with(&self, dir: Cluster, name: &str, options: RemoveOptions) -> Result<(), FsError> {
+        let _locked = self.lock_directories(&[dir]);
+        self.transaction(|| self.remove_inner(dir, name, options))
+    }
+
+    fn remove_inner(&self, dir: Cluster, name: &str, options: RemoveOptions) -> Result<(), FsError> {
+        let (entry, offset) = self.find_entry(dir, name)?;
+        if entry.is_dot() {
+            return Err(FsError::InvalidArgument);
+        }
+        if entry.attributes.is_read_only() || self.is_attached_journal(dir, &entry) {
+            return Err(FsError::AccessDenied);
+        }
+        if self.open_files.is_open(offset) {
+            return Err(FsError::Busy);
+        }
+        if entry.is_directory() && !self.is_empty_directory(entry.start_cluster)? {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+
+        if options.erase {
+            if entry.start_cluster.0 != 0 {
+                let clusters = self.chain(entry.start_cluster).collect::<Result<Vec<_>, _>>()?;
+                self.erase_clusters(&clusters)?;
+                self.barrier()?;
+            }
+            // Only the deleted marker stays, leaving nothing for undelete.
+            let mut cleared = [0u8; ENTRY_SIZE];
+            cleared[0] = DELETED_ENTRY;
+            self.write_at(offset, &cleared)?;
+        } else {
+            self.write_at(offset, &[DELETED_ENTRY])?;
+        }
+        self.barrier()?;
+        self.free_chain(entry.start_cluster)?;
+        self.barrier()
+    }
+
+    /// Rename `name` in directory `dir` to `new_name` in directory `new_dir`.
+    ///
+    /// Within a directory the name is rewritten in place. Across directories the
+    /// new entry is written before the old one is deleted, so the data stays
+    /// reachable whichever step a crash interrupts. Open files are refused
+    /// with `Busy`.
+    pub fn rename(
+        &self,
+        dir: Cluster,
+        name: &str,
+        new_dir: Cluster,
+        new_name: &str,
+    ) -> Result<(), FsError> {
+        // Two concurrent moves could each pass the cycle check and together
+        // detach a pair of directories from the tree, so they take turns.
+        let _moving = (dir != new_dir).then(|| self.moves.lock());
+        let _locked = self.lock_directories(&[dir, new_dir]);
+        // A directory moving elsewhere gets a new ".." entry. Its other
+        // holders never wait for a second lock, and the moves lock keeps
+        // other renames out, so waiting for it here cannot deadlock. It is
+        // taken before the transaction, which must not wait for it.
+        let _moved = match self.find_entry(dir, name) {
+            Ok((entry, _))
+                if dir != new_dir
+                    && entry.is_directory()
+                    && !entry.is_dot()
+                    && ![dir, new_dir].contains(&entry.start_cluster) =>
+            {
+                Some(self.lock_directories(&[entry.start_cluster]))
+            }
+            _ => None,
+        };
+        self.transaction(|| self.rename_inner(dir, name, new_dir, new_name))
+    }
+
+    fn rename_inner(
+        &self,
+        dir: Cluster,
+        name: &str,
+        new_dir: Cluster,
+        new_name: &str,
+    ) -> Result<(), FsError> {
+        check_name(new_name)?;
+        let (entry, offset) = self.find_entry(dir, name)?;
+        if entry.is_dot() {
+            return Err(FsError::InvalidArgument);
+        }
+        if self.is_attached_journal(dir, &entry) {
+            return Err(FsError::AccessDenied);
+        }
+        if self.open_files.is_open(offset) {
+            return Err(FsError::Busy);
+        }
+        self.check_absent(new_dir, new_name)?;
+        let renamed = DirectoryEntry {
+            file_name: DirectoryEntry::new(new_name, Cluster(0), 0, Attributes::empty()).file_name,
+            ..entry
+        };
+
+        if dir == new_dir {
+            self.update_entry(offset, &renamed)?;
+            return self.barrier();
+        }
+        if entry.is_directory() && self.is_within(new_dir, entry.start_cluster)? {
+            return Err(FsError::InvalidArgument); // A directory cannot move into itself.
+        }
+
+        // Copy the whole slot to keep the timestamps.
+        let mut bytes = [0u8; ENTRY_SIZE];
+        self.read_at(offset, &mut bytes)?;
+        renamed.write_into(&mut bytes);
+        let new_offset = self.add_entry_inner(new_dir, &renamed)?;
+        self.write_at(new_offset, &bytes)?;
+        self.barrier()?;
+
+        if entry.is_directory() {
+            // `rename` holds the lock of the moved directory.
+            let parent = if new_dir == self.root_cluster { Cluster(0) } else { new_dir };
+            let dot_dot = self.cluster_offset(entry.start_cluster) + ENTRY_SIZE as u64;
+            self.update_entry(dot_dot, &DirectoryEntry::dot_dot(parent))?;
+            self.barrier()?;
+        }
+
+        self.write_at(offset, &[DELETED_ENTRY])?;
+        self.barrier()
+    }
+
+    /// Lock directories against other changes until the guard is dropped.
+    pub(crate) fn lock_directories(&self, dirs: &[Cluster]) -> DirectoryGuard<'_> {
+        self.directories.lock(dirs)
+    }
+
+    // Check if `entry` of directory `dir` is the journal file in use. Its
+    // clusters are cached while attached, so it must stay where it is.
+    fn is_attached_journal(&self, dir: Cluster, entry: &DirectoryEntry) -> bool {
+        dir == self.root_cluster
+            && self.has_journal()
+            && entry.file_name().eq_ignore_ascii_case(JOURNAL_FILE)
+    }
+
+    // Check if directory `dir` holds nothing but its dot entries.
+    fn is_empty_directory(&self, dir: Cluster) -> Result<bool, FsError> {
+        for entry in DirectoryIterator::new(self, dir) {
+            if !entry?.is_dot() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // Check if directory `dir` is `ancestor` or lies below it, following "..".
+    fn is_within(&self, mut dir: Cluster, ancestor: Cluster) -> Result<bool, FsError> {
+        for _ in 0..self.cluster_count {
+            if dir == ancestor {
+                return Ok(true);
+            }
+            if dir == self.root_cluster || dir.0 == 0 {
+                return Ok(false);
+            }
+            dir = self.find_entry(dir, "..")?.0.start_cluster;
+        }
+        Err(FsError::InvalidArgument) // ".." links loop.
+    }
+
+    // Cluster holding a partition-relative offset in the data region.
+    fn cluster_of(&self, offset: u64) -> Cluster {
+        Cluster(((offset - self.data_start) / self.cluster_size as u64) as u32 + 2)
     }
 }
 
+// Clean-shutdown and no-error bits of FAT entry 1 for a FAT type, if it has them.
+pub(crate) fn dirty_flags(fat_type: FatType) -> Option<(u32, u32)> {
+    match fat_type {
+        FatType::Fat32 => Some((CLEAN_SHUTDOWN, NO_HARD_ERROR)),
+        FatType::Fat16 => Some((FAT16_CLEAN_SHUTDOWN, FAT16_NO_HARD_ERROR)),
+        FatType::Fat12 => None,
+    }
+}
+
+// Reject names that are not valid 8.3 names, rather than storing them
+// truncated: at most 8 characters, a dot and 3 more, none of them illegal.
+pub(crate) fn check_name(name: &str) -> Result<(), FsError> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    let valid = |part: &str, max: usize| {
+        part.len() <= max
+            && part
+                .bytes()
+                .all(|byte| byte.is_ascii() && byte >= 0x20 && !ILLEGAL_NAME_CHARS.contains(&byte))
+    };
+    if base.is_empty() || base.starts_with(' ') || !valid(base, 8) || !valid(ext, 3) {
+        return Err(FsError::InvalidArgument);
+    }
+    Ok(())
+}
@@ -0,0 +1,522 @@
+//! Filesystem consistency checker and repairer.
+
+use crate::boot_sector::FsInfo;
+use crate::directory::attribute::Attributes;
+use crate::directory::cluster::Cluster;
+use crate::directory::dir_entry::{DirectoryEntry, DELETED_ENTRY, ENTRY_SIZE};
+use crate::directory::name::ILLEGAL_NAME_CHARS;
+use crate::directory::table::FatValue;
+use crate::error::FsError;
+use crate::filesystem::{FatFileSystem, StorageDevice};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Directory holding recovered lost chains.
+pub const FOUND_DIR: &str = "FOUND.000";
+// Highest number fitting the FILEnnnn.CHK names.
+const LAST_CHK_NAME: u32 = 9999;
+
+/// Options for `check`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsckOptions {
+    pub repair: bool,           // Fix problems instead of only reporting them.
+    pub save_lost_chains: bool, // Keep lost chains as FOUND.000/FILEnnnn.CHK, don't free them.
+}
+
+/// A problem found by the checker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// FAT copy differing from the first one in `entries` entries.
+    FatMismatch { copy: u8, entries: u32 },
+    /// Malformed directory entry.
+    InvalidEntry { path: String, reason: &'static str },
+    /// Chain reaching a free, bad or out-of-range cluster.
+    BrokenChain { path: String, cluster: Cluster },
+    /// Chain looping back onto itself at `cluster`.
+    ChainLoop { path: String, cluster: Cluster },
+    /// Cluster already used by `other`.
+    CrossLinked {
+        path: String,
+        other: String,
+        cluster: Cluster,
+    },
+    /// Chain length not matching the file size.
+    ChainLength {
+        path: String,
+        expected: u32,
+        actual: u32,
+    },
+    /// Allocated chain not referenced by any directory entry.
+    LostChain { start: Cluster, clusters: u32 },
+    /// FSInfo free cluster count out of date.
+    FreeCount { recorded: u32, actual: u32 },
+}
+
+/// Result of a check.
+#[derive(Debug, Clone, Default)]
+pub struct FsckReport {
+    pub problems: Vec<Problem>,
+    pub files: u32,
+    pub directories: u32,
+    pub repaired: bool, // Problems were fixed on disk.
+}
+
+impl FsckReport {
+    /// Check if no problem was found.
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// How a chain walk ended.
+struct Walk {
+    clusters: Vec<Cluster>, // Clusters claimed, in chain order.
+    broken: bool,           // The walk stopped before a proper end of chain.
+}
+
+struct Checker<'a, S: StorageDevice> {
+    fs: &'a FatFileSystem<S>,
+    options: FsckOptions,
+    fat: Vec<u32>,      // First FAT copy.
+    owners: Vec<u32>,   // Per cluster: 0 if unreferenced, else index + 1 in `paths`.
+    paths: Vec<String>, // Owners of claimed clusters.
+    report: FsckReport,
+    write_error: Option<FsError>, // First failed FAT write during repair.
+}
+
+/// Check the volume and, if `options.repair` is set, fix what was found.
+pub fn check<S: StorageDevice>(
+    fs: &FatFileSystem<S>,
+    options: &FsckOptions,
+) -> Result<FsckReport, FsError> {
+    if options.repair && fs.is_read_only() {
+        return Err(FsError::ReadOnly);
+    }
+    let fat = FatValue::load_table(fs, 0)?;
+    let mut checker = Checker {
+        fs,
+        options: *options,
+        owners: vec![0; fat.len()],
+        fat,
+        paths: Vec::new(),
+        report: FsckReport::default(),
+        write_error: None,
+    };
+
+    checker.check_fat_copies()?;
+    checker.check_tree()?;
+    checker.check_lost_chains()?;
+    checker.check_free_count()?;
+    if let Some(err) = checker.write_error {
+        return Err(err);
+    }
+
+    if checker.options.repair {
+        // The volume is consistent again, whatever error was recorded.
+        checker.fs.clear_hard_error()?;
+        if !checker.report.is_clean() {
+            checker.fs.sync()?;
+            checker.report.repaired = true;
+        }
+    }
+    Ok(checker.report)
+}
+
+impl<S: StorageDevice> Checker<'_, S> {
+    fn problem(&mut self, problem: Problem) {
+        self.report.problems.push(problem);
+    }
+
+    fn set_fat(&mut self, cluster: Cluster, value: FatValue) {
+        self.fat[cluster.0 as usize] = value.to_raw();
+        if let Err(err) = FatValue::put(self.fs, cluster, value) {
+            self.write_error.get_or_insert(err);
+        }
+    }
+
+    // Compare every FAT copy with the first one, mirroring the first on repair.
+    fn check_fat_copies(&mut self) -> Result<(), FsError> {
+        for copy in 1..self.fs.num_fats {
+            let table = FatValue::load_table(self.fs, copy)?;
+            let entries = table.iter().zip(&self.fat).filter(|(a, b)| a != b).count() as u32;
+            if entries == 0 {
+                continue;
+            }
+            self.problem(Problem::FatMismatch { copy, entries });
+
+            if self.options.repair {
+                FatValue::store_table(self.fs, copy, &self.fat)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Follow a chain, claiming its clusters for `path`. Truncates it on repair
+    // where it loops, crosses another chain or leaves the data region.
+    fn claim_chain(&mut self, start: Cluster, path: &str) -> Walk {
+        self.paths.push(String::from(path));
+        let owner = self.paths.len() as u32;
+        let mut walk = Walk {
+            clusters: Vec::new(),
+            broken: false,
+        };
+        let mut cluster = start;
+
+        loop {
+            if !self.fs.is_data_cluster(cluster) {
+                self.problem(Problem::BrokenChain {
+                    path: String::from(path),
+                    cluster,
+                });
+                walk.broken = true;
+                break;
+            }
+            match self.owners[cluster.0 as usize] {
+                0 => {}
+                current if current == owner => {
+                    self.problem(Problem::ChainLoop {
+                        path: String::from(path),
+                        cluster,
+                    });
+                    walk.broken = true;
+                    break;
+                }
+                other => {
+                    let other = self.paths[other as usize - 1].clone();
+                    self.problem(Problem::CrossLinked {
+                        path: String::from(path),
+                        other,
+                        cluster,
+                    });
+                    walk.broken = true;
+                    break;
+                }
+            }
+
+            self.owners[cluster.0 as usize] = owner;
+            walk.clusters.push(cluster);
+            match FatValue::from_raw(self.fat[cluster.0 as usize]) {
+                FatValue::EndOfChain => break,
+                FatValue::Data(next) => cluster = Cluster(next),
+                FatValue::Free | FatValue::Bad => {
+                    // The entry of the last cluster is free or bad instead of ending the chain.
+                    self.problem(Problem::BrokenChain {
+                        path: String::from(path),
+                        cluster,
+                    });
+                    if self.options.repair {
+                        self.set_fat(cluster, FatValue::EndOfChain);
+                    }
+                    return walk;
+                }
+            }
+        }
+
+        // Cut the chain after the last cluster that was claimed.
+        if walk.broken && self.options.repair {
+            if let Some(&last) = walk.clusters.last() {
+                self.set_fat(last, FatValue::EndOfChain);
+            }
+        }
+        walk
+    }
+
+    // Walk every directory from the root, checking each entry and its chain.
+    fn check_tree(&mut self) -> Result<(), FsError> {
+        let root = self.fs.root_cluster;
+        let regions = if self.fs.is_fixed_root(root) {
+            vec![(self.fs.root_dir_start, self.fs.root_dir_size as usize)]
+        } else {
+            let walk = self.claim_chain(root, "/");
+            self.regions(&walk.clusters)
+        };
+        let mut pending = vec![(regions, String::new())];
+        self.report.directories += 1;
+
+        while let Some((regions, path)) = pending.pop() {
+            'regions: for (start, len) in regions {
+                let mut data = vec![0u8; len];
+                self.fs.read_at(start, &mut data)?;
+                for (i, raw) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+                    let raw: &[u8; ENTRY_SIZE] = raw.try_into().unwrap();
+                    if raw[0] == 0 {
+                        break 'regions;
+                    }
+                    let entry = DirectoryEntry::from_bytes(raw);
+                    if raw[0] == DELETED_ENTRY
+                        || raw[11] & 0x3F == 0x0F
+                        || entry.is_dot()
+                        || entry.is_volume_label()
+                    {
+                        continue;
+                    }
+
+                    let offset = start + (i * ENTRY_SIZE) as u64;
+                    let entry_path = format!("{}/{}", path, entry.file_name());
+                    if let Some(subdir) = self.check_entry(offset, entry, &entry_path)? {
+                        pending.push((self.regions(&subdir), entry_path));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Byte ranges of directory clusters.
+    fn regions(&self, clusters: &[Cluster]) -> Vec<(u64, usize)> {
+        let size = self.fs.cluster_size as usize;
+        clusters.iter().map(|&cluster| (self.fs.cluster_offset(cluster), size)).collect()
+    }
+
+    // Check one entry. Returns the clusters of a subdirectory to descend into.
+    fn check_entry(
+        &mut self,
+        offset: u64,
+        mut entry: DirectoryEntry,
+        path: &str,
+    ) -> Result<Option<Vec<Cluster>>, FsError> {
+        if let Some(reason) = invalid_reason(&entry) {
+            self.problem(Problem::InvalidEntry {
+                path: String::from(path),
+                reason,
+            });
+            if self.options.repair {
+                // Its chain is left unclaimed and recovered as a lost chain.
+                self.fs.write_at(offset, &[DELETED_ENTRY])?;
+            }
+            return Ok(None);
+        }
+
+        let mut dirty = false;
+        let walk = if entry.start_cluster.0 == 0 {
+            Walk {
+                clusters: Vec::new(),
+                broken: false,
+            }
+        } else {
+            self.claim_chain(entry.start_cluster, path)
+        };
+        if walk.clusters.is_empty() && entry.start_cluster.0 != 0 {
+            entry.start_cluster = Cluster(0); // Not even the first cluster was usable.
+            dirty = true;
+        }
+
+        if entry.is_directory() {
+            self.report.directories += 1;
+            if entry.file_size != 0 {
+                self.problem(Problem::InvalidEntry {
+                    path: String::from(path),
+                    reason: "directory with a size",
+                });
+                entry.file_size = 0;
+                dirty = true;
+            }
+            if dirty && self.options.repair {
+                self.fs.update_entry(offset, &entry)?;
+            }
+            return Ok(Some(walk.clusters).filter(|clusters| !clusters.is_empty()));
+        }
+
+        self.report.files += 1;
+        let cluster_size = self.fs.cluster_size as u64;
+        let expected = (entry.file_size as u64).div_ceil(cluster_size) as u32;
+        let actual = walk.clusters.len() as u32;
+        if expected != actual {
+            self.problem(Problem::ChainLength {
+                path: String::from(path),
+                expected,
+                actual,
+            });
+            if actual > expected {
+                // Too many clusters: release the tail of the chain. A check
+                // leaves it owned, the FAT still linking it to the file.
+                if self.options.repair {
+                    for &cluster in &walk.clusters[expected as usize..] {
+                        self.owners[cluster.0 as usize] = 0;
+                        self.set_fat(cluster, FatValue::Free);
+                    }
+                }
+                match expected {
+                    0 => entry.start_cluster = Cluster(0),
+                    n if self.options.repair => {
+                        self.set_fat(walk.clusters[n as usize - 1], FatValue::EndOfChain)
+                    }
+                    _ => {}
+                }
+            } else {
+                // Too few clusters: shrink the size to what the chain holds.
+                entry.file_size = (actual as u64 * cluster_size).min(entry.file_size as u64) as u32;
+            }
+            dirty = true;
+        }
+
+        if dirty && self.options.repair {
+            self.fs.update_entry(offset, &entry)?;
+        }
+        Ok(None)
+    }
+
+    // Find allocated clusters that no entry references and free or save them.
+    fn check_lost_chains(&mut self) -> Result<(), FsError> {
+        let lost = |checker: &Self, index: usize| {
+            let raw = checker.fat[index];
+            raw != 0 && FatValue::from_raw(raw) != FatValue::Bad && checker.owners[index] == 0
+        };
+
+        // Chain heads are lost clusters no other lost cluster points to.
+        let mut pointed = vec![false; self.fat.len()];
+        for index in 2..self.fat.len() {
+            if lost(self, index) {
+                if let FatValue::Data(next) = FatValue::from_raw(self.fat[index]) {
+                    if let Some(flag) = pointed.get_mut(next as usize) {
+                        *flag = true;
+                    }
+                }
+            }
+        }
+
+        let mut chains = Vec::new();
+        let mut visited = vec![false; self.fat.len()];
+        // Heads first, then whatever is left (chains that loop without a head).
+        for heads_only in [true, false] {
+            for start in 2..self.fat.len() {
+                if visited[start] || !lost(self, start) || (heads_only && pointed[start]) {
+                    continue;
+                }
+                let mut clusters = Vec::new();
+                let mut index = start;
+                loop {
+                    visited[index] = true;
+                    clusters.push(Cluster(index as u32));
+                    match FatValue::from_raw(self.fat[index]) {
+                        FatValue::Data(next) if (next as usize) < self.fat.len() => {
+                            let next = next as usize;
+                            if next < 2 || visited[next] || !lost(self, next) {
+                                break;
+                            }
+                            index = next;
+                        }
+                        _ => break,
+                    }
+                }
+                self.problem(Problem::LostChain {
+                    start: Cluster(start as u32),
+                    clusters: clusters.len() as u32,
+                });
+                chains.push(clusters);
+            }
+        }
+
+        if chains.is_empty() || !self.options.repair {
+            return Ok(());
+        }
+
+        let found = if self.options.save_lost_chains {
+            Some(self.found_directory()?)
+        } else {
+            None
+        };
+        let mut next_name = 0;
+        for clusters in chains {
+            let last = *clusters.last().unwrap();
+            match found {
+                Some(dir) => {
+                    self.set_fat(last, FatValue::EndOfChain);
+                    let size = (clusters.len() as u64 * self.fs.cluster_size as u64)
+                        .min(u32::MAX as u64) as u32;
+                    let name = loop {
+                        if next_name > LAST_CHK_NAME {
+                            return Err(FsError::NoSpace);
+                        }
+                        let name = format!("FILE{:04}.CHK", next_name);
+                        next_name += 1;
+                        match self.fs.check_absent(dir, &name) {
+                            Ok(()) => break name,
+                            Err(FsError::AlreadyExists) => {}
+                            Err(err) => return Err(err),
+                        }
+                    };
+                    let entry = DirectoryEntry::new(&name, clusters[0], size, Attributes::ARCHIVE);
+                    self.fs.add_entry(dir, &entry)?;
+                }
+                None => {
+                    for cluster in clusters {
+                        self.set_fat(cluster, FatValue::Free);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Locate or create FOUND.000 in the root directory.
+    fn found_directory(&mut self) -> Result<Cluster, FsError> {
+        let root = self.fs.root_cluster;
+        let entry = match self.fs.find_entry(root, FOUND_DIR) {
+            Ok((entry, _)) if entry.is_directory() => entry,
+            Ok(_) => return Err(FsError::AlreadyExists),
+            Err(FsError::NotFound) => self.fs.create_directory(root, FOUND_DIR)?,
+            Err(err) => return Err(err),
+        };
+        self.refresh_fat()?;
+        Ok(entry.start_cluster)
+    }
+
+    // Reload the in-memory FAT after allocations made through the filesystem.
+    fn refresh_fat(&mut self) -> Result<(), FsError> {
+        self.fat = FatValue::load_table(self.fs, 0)?;
+        Ok(())
+    }
+
+    // Compare the FSInfo free count with the FAT.
+    fn check_free_count(&mut self) -> Result<(), FsError> {
+        let Some(fs_info) = self.fs.fs_info() else {
+            return Ok(());
+        };
+        if self.options.repair {
+            self.refresh_fat()?;
+        }
+        let actual = self.fat[2..].iter().filter(|&&raw| raw == 0).count() as u32;
+        if fs_info.free_count != actual && fs_info.free_count != crate::boot_sector::FS_INFO_UNKNOWN
+        {
+            self.problem(Problem::FreeCount {
+                recorded: fs_info.free_count,
+                actual,
+            });
+            if self.options.repair {
+                self.fs.set_fs_info(FsInfo {
+                    free_count: actual,
+                    ..fs_info
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reason why a short entry is malformed, if it is.
+fn invalid_reason(entry: &DirectoryEntry) -> Option<&'static str> {
+    let name = &entry.file_name;
+    if name[0] == b' ' {
+        return Some("name starts with a space");
+    }
+    // 0x05 as first byte stands for a name starting with 0xE5.
+    if name
+        .iter()
+        .enumerate()
+        .any(|(i, &b)| b < 0x20 && !(i == 0 && b == 0x05))
+    {
+        return Some("control character in name");
+    }
+    if name.iter().any(|b| ILLEGAL_NAME_CHARS.contains(b)) {
+        return Some("illegal character in name");
+    }
+    if entry.attributes.bits() & 0xC0 != 0 {
+        return Some("reserved attribute bits set");
+    }
+    if entry.is_directory() && entry.start_cluster.0 == 0 {
+        return Some("directory without clusters");
+    }
+    None
+}
@@ -0,0 +1,201 @@
+//! Byte stream traits for files.
+//!
+//! `Read`, `Write` and `Seek` mirror the `embedded-io` traits so `no_std`
+//! code can use files generically. With the `std` feature, `File` also
+//! implements the `std::io` traits and `FsError` converts to `io::Error`.
+
+use crate::error::FsError;
+use crate::file::File;
+use crate::filesystem::StorageDevice;
+
+/// Position argument of `Seek::seek`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+/// Broad categories of errors, as in `embedded-io`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    Other,
+    NotFound,
+    PermissionDenied,
+    AlreadyExists,
+    InvalidInput,
+    InvalidData,
+    OutOfMemory,
+    WriteZero,
+}
+
+/// Error with a kind, as in `embedded-io`.
+pub trait Error: core::fmt::Debug {
+    fn kind(&self) -> ErrorKind;
+}
+
+impl Error for FsError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            FsError::NotFound => ErrorKind::NotFound,
+            FsError::ReadOnly | FsError::AccessDenied => ErrorKind::PermissionDenied,
+            FsError::AlreadyExists => ErrorKind::AlreadyExists,
+            FsError::InvalidArgument | FsError::OutOfBounds | FsError::IsDirectory => {
+                ErrorKind::InvalidInput
+            }
+            FsError::InvalidPartitionTable | FsError::InvalidBootSector => ErrorKind::InvalidData,
+            FsError::NoSpace => ErrorKind::WriteZero,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// Error type shared by the stream traits of a type.
+pub trait ErrorType {
+    type Error: Error;
+}
+
+pub trait Read: ErrorType {
+    /// Read into `buffer`, returning the number of bytes read (0 at the end).
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// Fill `buffer` entirely. Returns `None` if the stream ended first.
+    fn read_exact(&mut self, mut buffer: &mut [u8]) -> Result<Option<()>, Self::Error> {
+        while !buffer.is_empty() {
+            match self.read(buffer)? {
+                0 => return Ok(None),
+                count => buffer = &mut buffer[count..],
+            }
+        }
+        Ok(Some(()))
+    }
+}
+
+pub trait Write: ErrorType {
+    /// Write from `data`, returning the number of bytes written.
+    fn write(&mut self, data: &[u8]) -> Result<usize, Self::Error>;
+
+    /// Make written data durable.
+    fn flush(&mut self) -> Result<(), Self::Error>;
+
+    /// Write all of `data`.
+    fn write_all(&mut self, mut data: &[u8]) -> Result<(), Self::Error> {
+        while !data.is_empty() {
+            let count = self.write(data)?;
+            data = &data[count..];
+        }
+        Ok(())
+    }
+}
+
+pub trait Seek: ErrorType {
+    /// Move the position, returning the new one from the start.
+    fn seek(&mut self, position: SeekFrom) -> Result<u64, Self::Error>;
+}
+
+impl<S: StorageDevice> File<'_, S> {
+    // Resolve a `SeekFrom` against the current position and size.
+    fn resolve(&self, position: SeekFrom) -> Result<u32, FsError> {
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => (self.len() as u64).checked_add_signed(delta),
+            SeekFrom::Current(delta) => (self.position() as u64).checked_add_signed(delta),
+        };
+        // Files hold at most 4 GiB - 1 bytes.
+        target
+            .and_then(|target| u32::try_from(target).ok())
+            .ok_or(FsError::InvalidArgument)
+    }
+}
+
+impl<S: StorageDevice> ErrorType for File<'_, S> {
+    type Error = FsError;
+}
+
+impl<S: StorageDevice> Read for File<'_, S> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FsError> {
+        File::read(self, buffer)
+    }
+}
+
+impl<S: StorageDevice> Write for File<'_, S> {
+    fn write(&mut self, data: &[u8]) -> Result<usize, FsError> {
+        File::write(self, data)
+    }
+
+    fn flush(&mut self) -> Result<(), FsError> {
+        self.fs().barrier()
+    }
+}
+
+impl<S: StorageDevice> Seek for File<'_, S> {
+    fn seek(&mut self, position: SeekFrom) -> Result<u64, FsError> {
+        let position = self.resolve(position)?;
+        File::seek(self, position);
+        Ok(position as u64)
+    }
+}
+
+#[cfg(feature = "std")]
+mod std_io {
+    use super::{ErrorKind, SeekFrom};
+    use crate::error::FsError;
+    use crate::file::File;
+    use crate::filesystem::StorageDevice;
+    use std::io;
+
+    impl From<FsError> for io::Error {
+        fn from(err: FsError) -> Self {
+            let kind = match err {
+                FsError::ReadOnly => io::ErrorKind::ReadOnlyFilesystem,
+                FsError::IsDirectory => io::ErrorKind::IsADirectory,
+                FsError::DirectoryNotEmpty => io::ErrorKind::DirectoryNotEmpty,
+                FsError::NoSpace => io::ErrorKind::StorageFull,
+                FsError::Busy => io::ErrorKind::ResourceBusy,
+                FsError::OutOfBounds => io::ErrorKind::UnexpectedEof,
+                _ => match super::Error::kind(&err) {
+                    ErrorKind::NotFound => io::ErrorKind::NotFound,
+                    ErrorKind::PermissionDenied => io::ErrorKind::PermissionDenied,
+                    ErrorKind::AlreadyExists => io::ErrorKind::AlreadyExists,
+                    ErrorKind::InvalidInput => io::ErrorKind::InvalidInput,
+                    ErrorKind::InvalidData => io::ErrorKind::InvalidData,
+                    _ => io::ErrorKind::Other,
+                },
+            };
+            io::Error::new(kind, err)
+        }
+    }
+
+    impl From<io::SeekFrom> for SeekFrom {
+        fn from(position: io::SeekFrom) -> Self {
+            match position {
+                io::SeekFrom::Start(offset) => SeekFrom::Start(offset),
+                io::SeekFrom::End(delta) => SeekFrom::End(delta),
+                io::SeekFrom::Current(delta) => SeekFrom::Current(delta),
+            }
+        }
+    }
+
+    impl<S: StorageDevice> io::Read for File<'_, S> {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            Ok(File::read(self, buffer)?)
+        }
+    }
+
+    impl<S: StorageDevice> io::Write for File<'_, S> {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            Ok(File::write(self, data)?)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(self.fs().barrier()?)
+        }
+    }
+
+    impl<S: StorageDevice> io::Seek for File<'_, S> {
+        fn seek(&mut self, position: io::SeekFrom) -> io::Result<u64> {
+            Ok(super::Seek::seek(self, position.into())?)
+        }
+    }
+}
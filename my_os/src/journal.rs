@@ -0,0 +1,316 @@
+//! Optional write-ahead journal for FAT and directory updates.
+//!
+//! The journal lives in `JOURNAL.SYS`, a hidden system file in the root
+//! directory, so drivers that know nothing about it still see a valid
+//! volume. While a transaction is open, metadata writes are staged per
+//! 512-byte block. On commit the staged blocks are logged, the header is
+//! sealed with a CRC32, and only then are the blocks written in place.
+//! Mounting a volume left dirty replays a sealed transaction that was not
+//! fully applied.
+//!
+//! A transaction outgrowing the journal is committed in pieces: when a new
+//! block would not fit, the blocks staged so far are committed first, and
+//! from then on the staged blocks are also committed at each barrier. The
+//! pieces reach the disk in the order they were staged, so a crash leaves
+//! what an unjournaled volume would hold at one of those points.
+//!
+//! File data is not journaled: it is written straight to clusters that only
+//! become reachable once the transaction linking them commits.
+//!
+//! Layout, in 512-byte blocks: a header (magic, block count, CRC32), the
+//! target offsets (64 per block), then the block images.
+
+use crate::directory::attribute::Attributes;
+use crate::directory::cluster::Cluster;
+use crate::error::FsError;
+use crate::filesystem::{FatFileSystem, StorageDevice};
+use crate::partition::gpt::crc32;
+use alloc::collections::btree_map::{BTreeMap, Entry};
+use alloc::vec::Vec;
+
+/// Name of the journal file in the root directory.
+pub const JOURNAL_FILE: &str = "JOURNAL.SYS";
+/// Unit of journaled writes.
+pub const BLOCK_SIZE: usize = 512;
+
+const MAGIC: &[u8; 8] = b"FATJRNL1";
+const OFFSETS_PER_BLOCK: usize = BLOCK_SIZE / 8;
+
+type Block = [u8; BLOCK_SIZE];
+
+/// Journal state of a mounted volume.
+pub struct Journal {
+    blocks: Vec<u64>,              // Offset of every journal block, relative to the partition.
+    pending: BTreeMap<u64, Block>, // Staged block images by target offset.
+    open: bool,                    // A transaction is open.
+    split: bool,                   // The open transaction outgrew the journal.
+}
+
+impl Journal {
+    /// Load the journal of a volume whose journal file holds `clusters`.
+    pub(crate) fn load<S: StorageDevice>(
+        fs: &FatFileSystem<S>,
+        clusters: &[Cluster],
+    ) -> Result<Self, FsError> {
+        let per_cluster = fs.cluster_size as u64 / BLOCK_SIZE as u64;
+        let blocks = clusters
+            .iter()
+            .flat_map(|&cluster| {
+                let start = fs.cluster_offset(cluster);
+                (0..per_cluster).map(move |i| start + i * BLOCK_SIZE as u64)
+            })
+            .collect();
+        let mut journal = Self {
+            blocks,
+            pending: BTreeMap::new(),
+            open: false,
+            split: false,
+        };
+        journal.pending = journal.read_sealed(fs)?;
+        Ok(journal)
+    }
+
+    /// Number of blocks a single transaction can log.
+    pub fn capacity(&self) -> usize {
+        let usable = self.blocks.len().saturating_sub(1);
+        // Each run of 64 blocks needs one more block for its offsets.
+        usable - usable.div_ceil(OFFSETS_PER_BLOCK + 1)
+    }
+
+    /// Check if a transaction is open.
+    pub fn in_transaction(&self) -> bool {
+        self.open
+    }
+
+    /// Check if blocks are staged but not yet written in place.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    pub(crate) fn begin(&mut self) {
+        self.open = true;
+    }
+
+    /// Close the transaction; what it staged is left for `commit`.
+    pub(crate) fn end(&mut self) {
+        self.open = false;
+        self.split = false;
+    }
+
+    /// Commit what is staged at a barrier if the open transaction is being
+    /// committed in pieces.
+    pub(crate) fn checkpoint<S: StorageDevice>(
+        &mut self,
+        fs: &FatFileSystem<S>,
+    ) -> Result<(), FsError> {
+        match self.split {
+            true => self.commit(fs),
+            false => Ok(()),
+        }
+    }
+
+    /// Stage `data` at `offset`, reading partially covered blocks first.
+    pub(crate) fn stage<S: StorageDevice>(
+        &mut self,
+        fs: &FatFileSystem<S>,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), FsError> {
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset + done as u64;
+            let base = position - position % BLOCK_SIZE as u64;
+            let within = (position - base) as usize;
+            let count = (BLOCK_SIZE - within).min(data.len() - done);
+            if !self.pending.contains_key(&base) && self.pending.len() >= self.capacity() {
+                self.split = true;
+                self.commit(fs)?;
+            }
+            let block = match self.pending.entry(base) {
+                Entry::Occupied(staged) => staged.into_mut(),
+                Entry::Vacant(slot) => {
+                    let mut block = [0u8; BLOCK_SIZE];
+                    fs.device_read(base, &mut block)?;
+                    slot.insert(block)
+                }
+            };
+            block[within..within + count].copy_from_slice(&data[done..done + count]);
+            done += count;
+        }
+        Ok(())
+    }
+
+    /// Copy staged data over `buffer`, which was read from `offset`.
+    pub(crate) fn overlay(&self, offset: u64, buffer: &mut [u8]) {
+        let end = offset + buffer.len() as u64;
+        let first = offset - offset % BLOCK_SIZE as u64;
+        for (&base, block) in self.pending.range(first..end) {
+            let from = base.max(offset);
+            let to = (base + BLOCK_SIZE as u64).min(end);
+            buffer[(from - offset) as usize..(to - offset) as usize]
+                .copy_from_slice(&block[(from - base) as usize..(to - base) as usize]);
+        }
+    }
+
+    /// Mirror a write that bypassed the journal into staged blocks it overlaps.
+    pub(crate) fn patch(&mut self, offset: u64, data: &[u8]) {
+        let end = offset + data.len() as u64;
+        let first = offset - offset % BLOCK_SIZE as u64;
+        for (&base, block) in self.pending.range_mut(first..end) {
+            let from = base.max(offset);
+            let to = (base + BLOCK_SIZE as u64).min(end);
+            block[(from - base) as usize..(to - base) as usize]
+                .copy_from_slice(&data[(from - offset) as usize..(to - offset) as usize]);
+        }
+    }
+
+    /// Log the staged blocks, seal them, then write them in place.
+    ///
+    /// Staging never goes past `capacity`, so only a journal too small for
+    /// one block writes it in place without logging.
+    pub(crate) fn commit<S: StorageDevice>(
+        &mut self,
+        fs: &FatFileSystem<S>,
+    ) -> Result<(), FsError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let logged = self.pending.len() <= self.capacity();
+        if logged {
+            let (offsets, images) = self.encode();
+            let blocks = offsets.chunks(BLOCK_SIZE).chain(images.chunks(BLOCK_SIZE));
+            for (i, block) in blocks.enumerate() {
+                fs.device_write(self.blocks[1 + i], block)?;
+            }
+            fs.device_flush()?;
+            let crc = crc32(&[&offsets[..], &images[..]].concat());
+            self.write_header(fs, self.pending.len() as u32, crc)?;
+            fs.device_flush()?;
+        }
+
+        self.apply(fs)?;
+        fs.device_flush()?;
+        if logged {
+            self.write_header(fs, 0, 0)?;
+        }
+        Ok(())
+    }
+
+    // Write the staged blocks in place and forget them.
+    fn apply<S: StorageDevice>(&mut self, fs: &FatFileSystem<S>) -> Result<(), FsError> {
+        for (&offset, block) in &self.pending {
+            fs.device_write(offset, block)?;
+        }
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Write back a transaction found sealed at mount.
+    pub(crate) fn replay<S: StorageDevice>(
+        &mut self,
+        fs: &FatFileSystem<S>,
+    ) -> Result<(), FsError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.apply(fs)?;
+        fs.device_flush()?;
+        self.write_header(fs, 0, 0)?;
+        fs.device_flush()
+    }
+
+    /// Forget a sealed transaction found at mount, clearing it from the
+    /// header unless the volume is read-only.
+    pub(crate) fn discard<S: StorageDevice>(
+        &mut self,
+        fs: &FatFileSystem<S>,
+    ) -> Result<(), FsError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.pending.clear();
+        if fs.is_read_only() {
+            return Ok(());
+        }
+        self.write_header(fs, 0, 0)?;
+        fs.device_flush()
+    }
+
+    // Serialize the staged offsets (padded to whole blocks) and block images.
+    fn encode(&self) -> (Vec<u8>, Vec<u8>) {
+        let offset_blocks = self.pending.len().div_ceil(OFFSETS_PER_BLOCK);
+        let mut offsets = Vec::with_capacity(offset_blocks * BLOCK_SIZE);
+        let mut images = Vec::with_capacity(self.pending.len() * BLOCK_SIZE);
+        for (offset, block) in &self.pending {
+            offsets.extend_from_slice(&offset.to_le_bytes());
+            images.extend_from_slice(block);
+        }
+        offsets.resize(offset_blocks * BLOCK_SIZE, 0);
+        (offsets, images)
+    }
+
+    fn write_header<S: StorageDevice>(
+        &self,
+        fs: &FatFileSystem<S>,
+        count: u32,
+        crc: u32,
+    ) -> Result<(), FsError> {
+        let mut header = [0u8; BLOCK_SIZE];
+        header[0..8].copy_from_slice(MAGIC);
+        header[8..12].copy_from_slice(&count.to_le_bytes());
+        header[12..16].copy_from_slice(&crc.to_le_bytes());
+        fs.device_write(self.blocks[0], &header)
+    }
+
+    // Read a sealed transaction. Anything torn or unsealed counts as empty.
+    fn read_sealed<S: StorageDevice>(
+        &self,
+        fs: &FatFileSystem<S>,
+    ) -> Result<BTreeMap<u64, Block>, FsError> {
+        let mut pending = BTreeMap::new();
+        let Some(&header_offset) = self.blocks.first() else {
+            return Ok(pending);
+        };
+        let mut header = [0u8; BLOCK_SIZE];
+        fs.device_read(header_offset, &mut header)?;
+        let count = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        if &header[0..8] != MAGIC || count == 0 || count > self.capacity() {
+            return Ok(pending);
+        }
+
+        let offset_blocks = count.div_ceil(OFFSETS_PER_BLOCK);
+        let mut logged = vec![0u8; (offset_blocks + count) * BLOCK_SIZE];
+        for (i, block) in logged.chunks_mut(BLOCK_SIZE).enumerate() {
+            fs.device_read(self.blocks[1 + i], block)?;
+        }
+        if crc32(&logged) != crc {
+            return Ok(pending);
+        }
+
+        let (offsets, images) = logged.split_at(offset_blocks * BLOCK_SIZE);
+        for (offset, image) in offsets.chunks_exact(8).zip(images.chunks_exact(BLOCK_SIZE)) {
+            let offset = u64::from_le_bytes(offset.try_into().unwrap());
+            pending.insert(offset, image.try_into().unwrap());
+        }
+        Ok(pending)
+    }
+}
+
+/// Create the journal file with room for `size` bytes and start journaling.
+pub fn create<S: StorageDevice>(fs: &FatFileSystem<S>, size: u32) -> Result<(), FsError> {
+    if fs.has_journal() {
+        return Err(FsError::AlreadyExists);
+    }
+    let root = fs.root_cluster;
+    let mut file = fs.create_file(root, JOURNAL_FILE)?;
+    // Zeroed blocks hold no sealed header.
+    file.write(&vec![0u8; size.max(3 * BLOCK_SIZE as u32) as usize])?;
+
+    let mut entry = *file.entry();
+    entry.attributes = Attributes::HIDDEN | Attributes::SYSTEM | Attributes::ARCHIVE;
+    let (_, offset) = fs.find_entry(root, JOURNAL_FILE)?;
+    fs.update_entry(offset, &entry)?;
+    fs.barrier()?;
+    fs.attach_journal()
+}
@@ -2,6 +2,8 @@
 #![feature(alloc_error_handler)]
 #[macro_use]
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
 use uart_16550::SerialPort;
 use spin::Mutex;
@@ -17,13 +19,29 @@ lazy_static! {
 }
 
 // Modules
+pub mod asyncfs;
+pub mod boot_sector;
+pub mod defrag;
 pub mod directory;
+pub mod error;
+pub mod exfat;
+pub mod file;
 pub mod filesystem;
+pub mod fsck;
+pub mod io;
+pub mod journal;
+pub mod lock;
 pub mod memory;
+pub mod mkfs;
+pub mod partition;
 pub mod process;
 pub mod scheduler;
 pub mod syscall;
+pub mod undelete;
 pub mod slab;
+pub mod storage;
+pub mod volume;
+pub mod wipe;
 
 // Print macros for global access
 #[macro_export]
@@ -44,12 +62,18 @@ macro_rules! println {
 // Global allocator
 use crate::slab::GlobalAllocator;
 
-#[global_allocator]
+// Host builds (`std`) keep the system allocator.
+#[cfg_attr(not(feature = "std"), global_allocator)]
 static ALLOCATOR: GlobalAllocator = GlobalAllocator;
 
 // Allocation error handler
+#[cfg(not(feature = "std"))]
 #[alloc_error_handler]
 fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
     panic!("Allocation error: {:?}", layout);
 }
 
+// Declared last so the print macros above are in scope.
+#[cfg(test)]
+#[path = "tests/tests.rs"]
+mod tests;
@@ -0,0 +1,127 @@
+//! Locking for volumes shared between threads.
+//!
+//! Cluster allocation is serialized by a lock of its own, so two writers
+//! never take the same cluster. Operations that change a directory lock
+//! that directory for their whole duration, and open files register in a
+//! table that enforces their sharing mode. Reading file data and walking
+//! directories take none of these locks, so readers run in parallel.
+//!
+//! On a journaled volume, transactions run one at a time: each one commits
+//! only its own writes and returns its own errors. They are started after
+//! the directory locks are taken and never wait for one. Writes made
+//! outside any transaction while one is open still join it.
+
+use crate::directory::cluster::Cluster;
+use crate::error::FsError;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// How a file is opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    Read,  // Shared with other readers; writes fail with `AccessDenied`.
+    Write, // Exclusive: no other handle may be open on the file.
+}
+
+// Handles currently open on one entry.
+#[derive(Debug, Default)]
+struct Sharing {
+    readers: u32,
+    writer: bool,
+}
+
+/// Open files, keyed by the offset of their directory entry.
+pub(crate) struct OpenFiles {
+    files: Mutex<BTreeMap<u64, Sharing>>,
+}
+
+impl OpenFiles {
+    pub(crate) const fn new() -> Self {
+        Self { files: Mutex::new(BTreeMap::new()) }
+    }
+
+    /// Register a handle on the entry at `offset`, failing with `Busy` if
+    /// the mode conflicts with the handles already open.
+    pub(crate) fn acquire(&self, offset: u64, mode: OpenMode) -> Result<(), FsError> {
+        let mut files = self.files.lock();
+        let sharing = files.entry(offset).or_default();
+        match mode {
+            OpenMode::Read if !sharing.writer => sharing.readers += 1,
+            OpenMode::Write if !sharing.writer && sharing.readers == 0 => sharing.writer = true,
+            _ => return Err(FsError::Busy),
+        }
+        Ok(())
+    }
+
+    /// Drop a handle registered by `acquire`.
+    pub(crate) fn release(&self, offset: u64, mode: OpenMode) {
+        let mut files = self.files.lock();
+        if let Some(sharing) = files.get_mut(&offset) {
+            match mode {
+                OpenMode::Read => sharing.readers = sharing.readers.saturating_sub(1),
+                OpenMode::Write => sharing.writer = false,
+            }
+            if sharing.readers == 0 && !sharing.writer {
+                files.remove(&offset);
+            }
+        }
+    }
+
+    /// Check if any handle is open on the entry at `offset`.
+    pub(crate) fn is_open(&self, offset: u64) -> bool {
+        self.files.lock().contains_key(&offset)
+    }
+
+    /// Check if the entry at `offset` is open for writing.
+    pub(crate) fn is_open_for_writing(&self, offset: u64) -> bool {
+        self.files.lock().get(&offset).is_some_and(|sharing| sharing.writer)
+    }
+}
+
+/// Directories being changed, by first cluster.
+pub(crate) struct DirectoryLocks {
+    locked: Mutex<BTreeSet<u32>>,
+}
+
+impl DirectoryLocks {
+    pub(crate) const fn new() -> Self {
+        Self { locked: Mutex::new(BTreeSet::new()) }
+    }
+
+    /// Lock every directory of `dirs`, waiting until none of them is held.
+    ///
+    /// All of them are taken at once, so two operations locking the same
+    /// pair of directories cannot deadlock. A thread must not lock again
+    /// while it holds a guard.
+    pub(crate) fn lock(&self, dirs: &[Cluster]) -> DirectoryGuard<'_> {
+        let mut clusters: Vec<u32> = dirs.iter().map(|dir| dir.0).collect();
+        clusters.sort_unstable();
+        clusters.dedup();
+        loop {
+            {
+                let mut locked = self.locked.lock();
+                if clusters.iter().all(|cluster| !locked.contains(cluster)) {
+                    locked.extend(clusters.iter().copied());
+                    return DirectoryGuard { locks: self, clusters };
+                }
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Directories locked by `DirectoryLocks::lock`, released on drop.
+pub(crate) struct DirectoryGuard<'a> {
+    locks: &'a DirectoryLocks,
+    clusters: Vec<u32>,
+}
+
+impl Drop for DirectoryGuard<'_> {
+    fn drop(&mut self) {
+        let mut locked = self.locks.locked.lock();
+        for cluster in &self.clusters {
+            locked.remove(cluster);
+        }
+    }
+}
@@ -9,6 +9,10 @@ use uart_16550::SerialPort;
 use spin::Mutex;
 use lazy_static::lazy_static;
 use my_os::process::Process; // Correct import from crate root
+#[cfg(feature = "ramdisk_image")]
+use my_os::filesystem::FatFileSystem;
+#[cfg(feature = "ramdisk_image")]
+use my_os::storage::RamDisk;
 
 // Serial port for printing
 lazy_static! {
@@ -35,11 +39,31 @@ macro_rules! println {
     }};
 }
 
+// Bundled FAT32 image, copied into a static RAM disk buffer at boot
+#[cfg(feature = "ramdisk_image")]
+static RAMDISK_IMAGE: &[u8] = include_bytes!(env!("MY_OS_RAMDISK_IMAGE"));
+
+#[cfg(feature = "ramdisk_image")]
+static mut RAMDISK_BUFFER: [u8; RAMDISK_IMAGE.len()] = [0; RAMDISK_IMAGE.len()];
+
+// Mount the bundled image as a scratch filesystem
+#[cfg(feature = "ramdisk_image")]
+fn mount_ramdisk() -> FatFileSystem<RamDisk> {
+    let buffer = unsafe { &mut *core::ptr::addr_of_mut!(RAMDISK_BUFFER) };
+    let disk = RamDisk::with_image(buffer, RAMDISK_IMAGE).expect("RAM disk buffer too small");
+    FatFileSystem::mount(disk).expect("Bundled image is not a FAT32 volume")
+}
+
 // Entry point
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
     println!("Welcome to My OS!");
 
+    #[cfg(feature = "ramdisk_image")]
+    let ramdisk = mount_ramdisk();
+    #[cfg(feature = "ramdisk_image")]
+    println!("RAM disk mounted ({} byte clusters)", ramdisk.cluster_size);
+
     // Example process creation
     let mut process = Process::new("Init");  
     process.run();
@@ -0,0 +1,202 @@
+//! Format a storage device as a fresh FAT32 volume.
+
+use crate::boot_sector::{BootSector, FsInfo, BOOT_SIGNATURE};
+use crate::directory::attribute::Attributes;
+use crate::directory::cluster::Cluster;
+use crate::directory::dir_entry::DirectoryEntry;
+use crate::error::FsError;
+use crate::filesystem::{StorageDevice, MAX_CLUSTERS};
+
+const MIN_CLUSTERS: u32 = 65_525; // Fewer clusters would be detected as FAT16.
+const FS_INFO_SECTOR: u16 = 1;
+const BACKUP_BOOT_SECTOR: u16 = 6;
+const ZERO_CHUNK: usize = 64 * 1024;
+
+/// Label written when none is given.
+pub const NO_NAME: [u8; 11] = *b"NO NAME    ";
+
+/// Parameters for `format`.
+#[derive(Debug, Clone, Copy)]
+pub struct FormatOptions {
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: Option<u8>, // `None` picks the Microsoft default for the size.
+    pub volume_label: [u8; 11],
+    pub volume_id: u32, // Serial number, usually derived from the current time.
+    pub num_fats: u8,
+    pub reserved_sectors: u16,
+    pub media: u8,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            bytes_per_sector: 512,
+            sectors_per_cluster: None,
+            volume_label: NO_NAME,
+            volume_id: 0x1234_5678,
+            num_fats: 2,
+            reserved_sectors: 32,
+            media: 0xF8, // Fixed disk.
+        }
+    }
+}
+
+impl FormatOptions {
+    /// Set the volume label, upper-cased and padded to 11 bytes.
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.volume_label = [b' '; 11];
+        for (dst, src) in self.volume_label.iter_mut().zip(label.bytes()) {
+            *dst = src.to_ascii_uppercase();
+        }
+        self
+    }
+}
+
+/// Default sectors per cluster for a volume size, following Microsoft's FAT32 table
+/// (expressed for 512-byte sectors). Returns `None` for volumes too small for FAT32.
+pub fn default_sectors_per_cluster(total_sectors: u64, bytes_per_sector: u16) -> Option<u8> {
+    let size_512 = total_sectors * (bytes_per_sector as u64 / 512);
+    let cluster_bytes = match size_512 {
+        0..=66_600 => return None,         // Up to 32.5 MB.
+        66_601..=532_480 => 512,           // Up to 260 MB.
+        532_481..=16_777_216 => 4096,      // Up to 8 GB.
+        16_777_217..=33_554_432 => 8192,   // Up to 16 GB.
+        33_554_433..=67_108_864 => 16_384, // Up to 32 GB.
+        _ => 32_768,
+    };
+    Some((cluster_bytes / bytes_per_sector as u32).max(1) as u8)
+}
+
+/// Compute the boot sector for a volume of `size` bytes.
+pub fn layout(size: u64, options: &FormatOptions) -> Result<BootSector, FsError> {
+    let bytes_per_sector = options.bytes_per_sector;
+    if !bytes_per_sector.is_power_of_two() || !(512..=4096).contains(&bytes_per_sector) {
+        return Err(FsError::InvalidArgument);
+    }
+    // The backup boot sector and its FSInfo copy live at sectors 6 and 7.
+    if options.reserved_sectors < BACKUP_BOOT_SECTOR + 2 || options.num_fats == 0 {
+        return Err(FsError::InvalidArgument);
+    }
+
+    let total_sectors = size / bytes_per_sector as u64;
+    let total_sectors = u32::try_from(total_sectors).map_err(|_| FsError::InvalidArgument)?;
+    let sectors_per_cluster = match options.sectors_per_cluster {
+        Some(spc) if spc.is_power_of_two() => spc,
+        Some(_) => return Err(FsError::InvalidArgument),
+        None => default_sectors_per_cluster(total_sectors as u64, bytes_per_sector)
+            .ok_or(FsError::InvalidArgument)?,
+    };
+
+    // FAT size as computed by the Microsoft specification.
+    let data_and_fats = (total_sectors as u64).saturating_sub(options.reserved_sectors as u64);
+    let divisor = (256 * sectors_per_cluster as u64 * (bytes_per_sector as u64 / 512)
+        + options.num_fats as u64)
+        / 2;
+    let fat_size = data_and_fats.div_ceil(divisor) as u32;
+
+    let boot = BootSector {
+        oem_name: *b"MY_OS   ",
+        bytes_per_sector,
+        sectors_per_cluster,
+        reserved_sectors: options.reserved_sectors,
+        num_fats: options.num_fats,
+        root_entry_count: 0,
+        total_sectors_16: 0,
+        media: options.media,
+        fat_size_16: 0,
+        sectors_per_track: 63,
+        num_heads: 255,
+        hidden_sectors: 0,
+        total_sectors_32: total_sectors,
+        fat_size_32: fat_size,
+        ext_flags: 0, // FAT copies are mirrored.
+        fs_version: 0,
+        root_cluster: 2,
+        fs_info_sector: FS_INFO_SECTOR,
+        backup_boot_sector: BACKUP_BOOT_SECTOR,
+        drive_number: 0x80,
+        boot_signature: 0x29,
+        volume_id: options.volume_id,
+        volume_label: options.volume_label,
+        fs_type: *b"FAT32   ",
+    };
+
+    if boot.first_data_sector() >= total_sectors {
+        return Err(FsError::InvalidArgument);
+    }
+    let clusters = boot.cluster_count();
+    if !(MIN_CLUSTERS..=MAX_CLUSTERS).contains(&clusters) {
+        return Err(FsError::InvalidArgument);
+    }
+    Ok(boot)
+}
+
+/// Write zeros over `len` bytes starting at `offset`.
+fn zero_fill<S: StorageDevice>(device: &S, mut offset: u64, len: u64) -> Result<(), FsError> {
+    let zeros = vec![0u8; ZERO_CHUNK];
+    let end = offset + len;
+    while offset < end {
+        let chunk = (end - offset).min(ZERO_CHUNK as u64) as usize;
+        device.write(offset, &zeros[..chunk])?;
+        offset += chunk as u64;
+    }
+    Ok(())
+}
+
+/// Format `device` (of `size` bytes) as an empty FAT32 volume.
+pub fn format<S: StorageDevice>(
+    device: &S,
+    size: u64,
+    options: &FormatOptions,
+) -> Result<BootSector, FsError> {
+    let boot = layout(size, options)?;
+    let sector_size = boot.bytes_per_sector as u64;
+
+    // Reserved region: boot sector, FSInfo and their backups.
+    zero_fill(device, 0, boot.reserved_sectors as u64 * sector_size)?;
+    let mut boot_bytes = vec![0u8; sector_size as usize];
+    boot_bytes[..512].copy_from_slice(&boot.to_bytes());
+    boot_bytes[sector_size as usize - 2..].copy_from_slice(&BOOT_SIGNATURE);
+
+    let fs_info = FsInfo {
+        free_count: boot.cluster_count() - 1, // The root directory uses one cluster.
+        next_free: 3,
+    };
+    let mut fs_info_bytes = vec![0u8; sector_size as usize];
+    fs_info_bytes[..512].copy_from_slice(&fs_info.to_bytes());
+
+    for base in [0, BACKUP_BOOT_SECTOR as u64] {
+        device.write(base * sector_size, &boot_bytes)?;
+        device.write((base + FS_INFO_SECTOR as u64) * sector_size, &fs_info_bytes)?;
+        // Third boot sector, unused but signed.
+        device.write((base + 3) * sector_size - 2, &BOOT_SIGNATURE)?;
+    }
+
+    // FATs: all free except the media entry, the clean-shutdown entry and the root cluster.
+    let fat_start = boot.reserved_sectors as u64 * sector_size;
+    let fat_bytes = boot.fat_size() as u64 * sector_size;
+    zero_fill(device, fat_start, fat_bytes * boot.num_fats as u64)?;
+    let mut reserved = [0u8; 12];
+    reserved[0..4].copy_from_slice(&(0x0FFF_FF00 | boot.media as u32).to_le_bytes());
+    reserved[4..8].copy_from_slice(&0x0FFF_FFFFu32.to_le_bytes());
+    reserved[8..12].copy_from_slice(&0x0FFF_FFFFu32.to_le_bytes());
+    for copy in 0..boot.num_fats as u64 {
+        device.write(fat_start + copy * fat_bytes, &reserved)?;
+    }
+
+    // Empty root directory, holding only the volume label if one was given.
+    let root_offset = boot.first_data_sector() as u64 * sector_size;
+    zero_fill(device, root_offset, boot.cluster_size() as u64)?;
+    if boot.volume_label != NO_NAME {
+        let label = DirectoryEntry {
+            file_name: boot.volume_label,
+            attributes: Attributes::VOLUME_ID,
+            start_cluster: Cluster(0),
+            file_size: 0,
+        };
+        device.write(root_offset, &label.to_bytes())?;
+    }
+
+    device.flush()?;
+    Ok(boot)
+}
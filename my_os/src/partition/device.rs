@@ -0,0 +1,80 @@
+//! Bounds-checked view of a partition as its own storage device.
+
+use crate::error::FsError;
+use crate::filesystem::StorageDevice;
+use crate::partition::Partition;
+
+/// Storage device restricted to one partition of a parent device.
+///
+/// Offsets are relative to the partition start, so a filesystem mounted on it
+/// can assume offset zero and can never touch a neighboring partition.
+pub struct PartitionDevice<S: StorageDevice> {
+    parent: S,
+    partition: Partition,
+}
+
+impl<S: StorageDevice> PartitionDevice<S> {
+    /// Wrap `parent`, checking the partition fits inside it when its size is known.
+    pub fn new(parent: S, partition: Partition) -> Result<Self, FsError> {
+        let end = partition
+            .start
+            .checked_add(partition.length)
+            .ok_or(FsError::OutOfBounds)?;
+        if parent.size().is_some_and(|size| end > size) {
+            return Err(FsError::OutOfBounds);
+        }
+        Ok(Self { parent, partition })
+    }
+
+    /// Byte range of the partition on the parent device.
+    pub fn partition(&self) -> Partition {
+        self.partition
+    }
+
+    /// Access the parent device.
+    pub fn parent(&self) -> &S {
+        &self.parent
+    }
+
+    /// Release the parent device.
+    pub fn into_inner(self) -> S {
+        self.parent
+    }
+
+    // Translate a partition-relative access to a parent offset.
+    fn translate(&self, offset: u64, len: usize) -> Result<u64, FsError> {
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.partition.length => Ok(self.partition.start + offset),
+            _ => Err(FsError::OutOfBounds),
+        }
+    }
+}
+
+impl<S: StorageDevice> StorageDevice for PartitionDevice<S> {
+    fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<(), FsError> {
+        let offset = self.translate(offset, buffer.len())?;
+        self.parent.read(offset, buffer)
+    }
+
+    fn write(&self, offset: u64, buffer: &[u8]) -> Result<(), FsError> {
+        let offset = self.translate(offset, buffer.len())?;
+        self.parent.write(offset, buffer)
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.partition.length)
+    }
+
+    fn flush(&self) -> Result<(), FsError> {
+        self.parent.flush()
+    }
+
+    fn discard(&self, offset: u64, len: u64) -> Result<bool, FsError> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.partition.length => {
+                self.parent.discard(self.partition.start + offset, len)
+            }
+            _ => Err(FsError::OutOfBounds),
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! GPT partition table parsing.
+
+use crate::error::FsError;
+use crate::filesystem::StorageDevice;
+use crate::partition::{Mbr, Partition, SECTOR_SIZE};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+const SIGNATURE: &[u8; 8] = b"EFI PART";
+const MIN_HEADER_SIZE: usize = 92;
+const MIN_ENTRY_SIZE: usize = 128;
+const MAX_ENTRY_ARRAY: usize = 1024 * 1024; // Sanity limit for the partition entry array.
+
+/// A GUID as stored on disk (first three fields little-endian).
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub const ZERO: Guid = Guid([0; 16]);
+
+    /// Build a GUID from its textual fields, e.g. `EBD0A0A2-B9E5-4433-87C0-68B6B72699C7`.
+    pub const fn from_fields(a: u32, b: u16, c: u16, d: [u8; 8]) -> Self {
+        let a = a.to_le_bytes();
+        let b = b.to_le_bytes();
+        let c = c.to_le_bytes();
+        Guid([
+            a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d[0], d[1], d[2], d[3], d[4], d[5],
+            d[6], d[7],
+        ])
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Self::ZERO
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let g = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-",
+            u32::from_le_bytes([g[0], g[1], g[2], g[3]]),
+            u16::from_le_bytes([g[4], g[5]]),
+            u16::from_le_bytes([g[6], g[7]])
+        )?;
+        for (i, byte) in g[8..].iter().enumerate() {
+            if i == 2 {
+                f.write_str("-")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Microsoft Basic Data partition (FAT, exFAT, NTFS).
+pub const BASIC_DATA: Guid = Guid::from_fields(
+    0xEBD0_A0A2,
+    0xB9E5,
+    0x4433,
+    [0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7],
+);
+
+/// EFI System Partition (always FAT).
+pub const EFI_SYSTEM: Guid = Guid::from_fields(
+    0xC12A_7328,
+    0xF81F,
+    0x11D2,
+    [0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B],
+);
+
+/// CRC32 (IEEE 802.3) lookup table.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC32 as used by the GPT header and entry array.
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+fn le_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn guid_at(bytes: &[u8], offset: usize) -> Guid {
+    Guid(bytes[offset..offset + 16].try_into().unwrap())
+}
+
+/// Validated GPT header.
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    alternate_lba: u64,
+    first_usable_lba: u64,
+    last_usable_lba: u64,
+    disk_guid: Guid,
+    entries_lba: u64,
+    entry_count: u32,
+    entry_size: u32,
+    entries_crc32: u32,
+}
+
+impl Header {
+    /// Read and validate the header stored at `lba`.
+    fn read<S: StorageDevice>(device: &S, lba: u64) -> Result<Self, FsError> {
+        let mut sector = [0u8; SECTOR_SIZE as usize];
+        device.read(lba * SECTOR_SIZE, &mut sector)?;
+
+        let header_size = le_u32(&sector, 12) as usize;
+        if &sector[0..8] != SIGNATURE || !(MIN_HEADER_SIZE..=sector.len()).contains(&header_size) {
+            return Err(FsError::InvalidPartitionTable);
+        }
+
+        // The CRC covers the header with its own CRC field zeroed.
+        let stored_crc = le_u32(&sector, 16);
+        sector[16..20].fill(0);
+        if crc32(&sector[..header_size]) != stored_crc || le_u64(&sector, 24) != lba {
+            return Err(FsError::InvalidPartitionTable);
+        }
+
+        let header = Self {
+            alternate_lba: le_u64(&sector, 32),
+            first_usable_lba: le_u64(&sector, 40),
+            last_usable_lba: le_u64(&sector, 48),
+            disk_guid: guid_at(&sector, 56),
+            entries_lba: le_u64(&sector, 72),
+            entry_count: le_u32(&sector, 80),
+            entry_size: le_u32(&sector, 84),
+            entries_crc32: le_u32(&sector, 88),
+        };
+
+        let entry_size = header.entry_size as usize;
+        let array_size = entry_size.checked_mul(header.entry_count as usize);
+        if entry_size < MIN_ENTRY_SIZE
+            || !entry_size.is_multiple_of(8)
+            || array_size.is_none_or(|size| size > MAX_ENTRY_ARRAY)
+            || header.first_usable_lba > header.last_usable_lba
+        {
+            return Err(FsError::InvalidPartitionTable);
+        }
+        Ok(header)
+    }
+
+    /// Read the partition entry array and check its CRC.
+    fn read_entries<S: StorageDevice>(&self, device: &S) -> Result<Vec<GptPartition>, FsError> {
+        let entry_size = self.entry_size as usize;
+        let mut entries = vec![0u8; entry_size * self.entry_count as usize];
+        device.read(self.entries_lba * SECTOR_SIZE, &mut entries)?;
+        if crc32(&entries) != self.entries_crc32 {
+            return Err(FsError::InvalidPartitionTable);
+        }
+
+        let mut partitions = Vec::new();
+        for (i, entry) in entries.chunks_exact(entry_size).enumerate() {
+            let type_guid = guid_at(entry, 0);
+            if type_guid.is_zero() {
+                continue; // Unused entry.
+            }
+
+            let first_lba = le_u64(entry, 32);
+            let last_lba = le_u64(entry, 40);
+            if first_lba < self.first_usable_lba
+                || last_lba > self.last_usable_lba
+                || last_lba < first_lba
+            {
+                return Err(FsError::OutOfBounds);
+            }
+            let partition = Partition::from_lba(first_lba, last_lba - first_lba + 1, device.size())
+                .ok_or(FsError::OutOfBounds)?;
+
+            // Names are UTF-16LE, NUL padded.
+            let units = entry[56..128]
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .take_while(|&unit| unit != 0);
+            let name = char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect();
+
+            partitions.push(GptPartition {
+                number: i as u32 + 1,
+                type_guid,
+                unique_guid: guid_at(entry, 16),
+                attributes: le_u64(entry, 48),
+                name,
+                partition,
+            });
+        }
+        Ok(partitions)
+    }
+}
+
+/// A partition described by a GPT entry.
+#[derive(Debug, Clone)]
+pub struct GptPartition {
+    pub number: u32,     // 1-based index in the entry array.
+    pub type_guid: Guid, // Partition type.
+    pub unique_guid: Guid,
+    pub attributes: u64,
+    pub name: String,
+    pub partition: Partition,
+}
+
+impl GptPartition {
+    /// Check if the partition type may hold a FAT32 volume.
+    pub fn is_fat_candidate(&self) -> bool {
+        self.type_guid == BASIC_DATA || self.type_guid == EFI_SYSTEM
+    }
+
+    /// Check if the partition is an EFI System Partition.
+    pub fn is_efi_system(&self) -> bool {
+        self.type_guid == EFI_SYSTEM
+    }
+}
+
+/// Parsed GUID partition table.
+#[derive(Debug, Clone)]
+pub struct Gpt {
+    pub disk_guid: Guid,
+    pub partitions: Vec<GptPartition>,
+    pub used_backup: bool, // The primary header or entries were corrupt.
+}
+
+impl Gpt {
+    /// Validate the protective MBR and read the primary GPT, falling back to the backup.
+    pub fn read<S: StorageDevice>(device: &S) -> Result<Self, FsError> {
+        if !Mbr::read(device)?.is_protective() {
+            return Err(FsError::InvalidPartitionTable);
+        }
+
+        let primary = Header::read(device, 1);
+        if let Ok(header) = primary {
+            if let Ok(partitions) = header.read_entries(device) {
+                return Ok(Self {
+                    disk_guid: header.disk_guid,
+                    partitions,
+                    used_backup: false,
+                });
+            }
+        }
+
+        // A corrupt primary header cannot be trusted to locate the backup.
+        let backup_lba = match primary {
+            Ok(header) => header.alternate_lba,
+            Err(_) => match device.size() {
+                Some(size) if size >= 2 * SECTOR_SIZE => size / SECTOR_SIZE - 1,
+                _ => return Err(FsError::InvalidPartitionTable),
+            },
+        };
+        let header = Header::read(device, backup_lba)?;
+        let partitions = header.read_entries(device)?;
+        Ok(Self {
+            disk_guid: header.disk_guid,
+            partitions,
+            used_backup: true,
+        })
+    }
+
+    /// Look up a partition by its 1-based entry number.
+    pub fn get(&self, number: u32) -> Result<&GptPartition, FsError> {
+        self.partitions
+            .iter()
+            .find(|p| p.number == number)
+            .ok_or(FsError::NotFound)
+    }
+
+    /// Iterate over Basic Data and EFI System partitions.
+    pub fn fat_partitions(&self) -> impl Iterator<Item = &GptPartition> {
+        self.partitions.iter().filter(|p| p.is_fat_candidate())
+    }
+}
@@ -0,0 +1,193 @@
+//! MBR partition table parsing.
+
+use crate::error::FsError;
+use crate::filesystem::StorageDevice;
+use crate::partition::{Partition, SECTOR_SIZE};
+use alloc::vec::Vec;
+
+const SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const TABLE_OFFSET: usize = 446;
+const ENTRY_SIZE: usize = 16;
+const MAX_LOGICAL_PARTITIONS: usize = 128; // Guards against looping EBR chains.
+
+/// Partition type ids of interest.
+pub const TYPE_EMPTY: u8 = 0x00;
+pub const TYPE_FAT32_CHS: u8 = 0x0B;
+pub const TYPE_FAT32_LBA: u8 = 0x0C;
+pub const TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+
+/// Check if a partition type id denotes an extended partition.
+pub fn is_extended(partition_type: u8) -> bool {
+    matches!(partition_type, 0x05 | 0x0F | 0x85)
+}
+
+/// A primary or logical partition described by the MBR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MbrPartition {
+    pub number: u8,         // 1-4 for primary partitions, 5 and up for logical ones.
+    pub partition_type: u8, // System id byte.
+    pub bootable: bool,     // Active flag (0x80).
+    pub partition: Partition,
+}
+
+impl MbrPartition {
+    /// Check if the partition is tagged as FAT32.
+    pub fn is_fat32(&self) -> bool {
+        matches!(self.partition_type, TYPE_FAT32_CHS | TYPE_FAT32_LBA)
+    }
+
+    /// Check if the partition is a logical partition inside an extended one.
+    pub fn is_logical(&self) -> bool {
+        self.number > 4
+    }
+}
+
+/// Raw 16-byte partition table entry.
+#[derive(Debug, Clone, Copy)]
+struct RawEntry {
+    status: u8,
+    partition_type: u8,
+    start_lba: u32,
+    sector_count: u32,
+}
+
+impl RawEntry {
+    fn parse(bytes: &[u8]) -> Self {
+        Self {
+            status: bytes[0],
+            partition_type: bytes[4],
+            start_lba: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            sector_count: u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.partition_type == TYPE_EMPTY || self.sector_count == 0
+    }
+}
+
+/// Read a boot record sector and return its four table entries.
+fn read_table<S: StorageDevice>(device: &S, lba: u64) -> Result<[RawEntry; 4], FsError> {
+    let mut sector = [0u8; SECTOR_SIZE as usize];
+    device.read(lba * SECTOR_SIZE, &mut sector)?;
+    if sector[510..512] != SIGNATURE {
+        return Err(FsError::InvalidPartitionTable);
+    }
+
+    let entry = |i: usize| RawEntry::parse(&sector[TABLE_OFFSET + i * ENTRY_SIZE..][..ENTRY_SIZE]);
+    Ok([entry(0), entry(1), entry(2), entry(3)])
+}
+
+/// Parsed MBR with primary and logical partitions.
+#[derive(Debug, Clone)]
+pub struct Mbr {
+    pub partitions: Vec<MbrPartition>,
+}
+
+impl Mbr {
+    /// Read sector 0 of `device` and walk any extended partition chain.
+    pub fn read<S: StorageDevice>(device: &S) -> Result<Self, FsError> {
+        let device_size = device.size();
+        let mut partitions = Vec::new();
+        let mut extended = None;
+
+        for (i, entry) in read_table(device, 0)?.iter().enumerate() {
+            if entry.is_empty() {
+                continue;
+            }
+            let mut sector_count = entry.sector_count as u64;
+            if entry.partition_type == TYPE_GPT_PROTECTIVE {
+                // Protective entries may claim 0xFFFFFFFF sectors on large disks.
+                if let Some(size) = device_size {
+                    let available = (size / SECTOR_SIZE).saturating_sub(entry.start_lba as u64);
+                    sector_count = sector_count.min(available);
+                }
+            }
+            let partition = Partition::from_lba(entry.start_lba as u64, sector_count, device_size)
+                .ok_or(FsError::OutOfBounds)?;
+            if is_extended(entry.partition_type) {
+                if extended.replace(partition).is_some() {
+                    return Err(FsError::InvalidPartitionTable); // Only one extended partition is allowed.
+                }
+                continue;
+            }
+            partitions.push(MbrPartition {
+                number: i as u8 + 1,
+                partition_type: entry.partition_type,
+                bootable: entry.status & 0x80 != 0,
+                partition,
+            });
+        }
+
+        if let Some(extended) = extended {
+            Self::read_logical(device, extended, &mut partitions)?;
+        }
+
+        Ok(Self { partitions })
+    }
+
+    /// Follow the EBR linked list of an extended partition.
+    fn read_logical<S: StorageDevice>(
+        device: &S,
+        extended: Partition,
+        partitions: &mut Vec<MbrPartition>,
+    ) -> Result<(), FsError> {
+        let extended_lba = extended.start / SECTOR_SIZE;
+        let mut ebr_offset = 0u64; // Relative to the start of the extended partition.
+
+        for number in 5..5 + MAX_LOGICAL_PARTITIONS {
+            let ebr_lba = extended_lba + ebr_offset;
+            let [logical, next, ..] = read_table(device, ebr_lba)?;
+
+            if !logical.is_empty() {
+                let partition = Partition::from_lba(
+                    ebr_lba + logical.start_lba as u64,
+                    logical.sector_count as u64,
+                    None,
+                )
+                .filter(|p| p.start >= extended.start && p.end() <= extended.end())
+                .ok_or(FsError::OutOfBounds)?;
+                partitions.push(MbrPartition {
+                    number: number as u8,
+                    partition_type: logical.partition_type,
+                    bootable: logical.status & 0x80 != 0,
+                    partition,
+                });
+            }
+
+            if next.is_empty() {
+                return Ok(());
+            }
+            // Each link must move forward, which also rules out cycles.
+            if next.start_lba as u64 <= ebr_offset
+                || next.start_lba as u64 * SECTOR_SIZE >= extended.length
+            {
+                return Err(FsError::InvalidPartitionTable);
+            }
+            ebr_offset = next.start_lba as u64;
+        }
+
+        Err(FsError::InvalidPartitionTable)
+    }
+
+    /// Look up a partition by its number (1-4 primary, 5+ logical).
+    pub fn get(&self, number: u8) -> Result<MbrPartition, FsError> {
+        self.partitions
+            .iter()
+            .find(|p| p.number == number)
+            .copied()
+            .ok_or(FsError::NotFound)
+    }
+
+    /// Iterate over partitions tagged as FAT32.
+    pub fn fat32_partitions(&self) -> impl Iterator<Item = &MbrPartition> {
+        self.partitions.iter().filter(|p| p.is_fat32())
+    }
+
+    /// Check if the MBR is a GPT protective MBR.
+    pub fn is_protective(&self) -> bool {
+        self.partitions
+            .iter()
+            .any(|p| p.partition_type == TYPE_GPT_PROTECTIVE)
+    }
+}
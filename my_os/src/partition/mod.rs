@@ -0,0 +1,37 @@
+//! Partition table support.
+
+pub mod device;
+pub mod gpt;
+pub mod mbr;
+
+pub use device::PartitionDevice;
+pub use gpt::{Gpt, GptPartition, Guid};
+pub use mbr::{Mbr, MbrPartition};
+
+/// Size of a logical block as addressed by partition tables.
+pub const SECTOR_SIZE: u64 = 512;
+
+/// Byte range of a partition on its parent device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Partition {
+    pub start: u64,  // Offset in bytes from the start of the device.
+    pub length: u64, // Length in bytes.
+}
+
+impl Partition {
+    /// Build a partition from an LBA range, checking it fits in `device_size` when known.
+    pub fn from_lba(start_lba: u64, sector_count: u64, device_size: Option<u64>) -> Option<Self> {
+        let start = start_lba.checked_mul(SECTOR_SIZE)?;
+        let length = sector_count.checked_mul(SECTOR_SIZE)?;
+        let end = start.checked_add(length)?;
+        match device_size {
+            Some(size) if end > size => None,
+            _ => Some(Self { start, length }),
+        }
+    }
+
+    /// Offset of the first byte after the partition.
+    pub fn end(&self) -> u64 {
+        self.start + self.length
+    }
+}
@@ -0,0 +1,124 @@
+//! File-backed storage device for disk images on the host.
+
+use crate::error::FsError;
+use crate::filesystem::StorageDevice;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+/// Storage device backed by a regular file, e.g. an image produced by `mkfs.vfat`.
+#[derive(Debug)]
+pub struct FileStorage {
+    file: File,
+    read_only: bool,
+}
+
+impl FileStorage {
+    /// Open an existing image for reading and writing.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(Self::from_file(file, false))
+    }
+
+    /// Open an existing image without write access.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).open(path)?;
+        Ok(Self::from_file(file, true))
+    }
+
+    /// Create (or truncate) an image of `size` bytes filled with zeros.
+    pub fn create<P: AsRef<Path>>(path: P, size: u64) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(size)?;
+        Ok(Self::from_file(file, false))
+    }
+
+    /// Wrap an already opened file.
+    pub fn from_file(file: File, read_only: bool) -> Self {
+        Self { file, read_only }
+    }
+
+    /// Check if the image was opened without write access.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Release the underlying file.
+    pub fn into_inner(self) -> File {
+        self.file
+    }
+}
+
+impl StorageDevice for FileStorage {
+    fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<(), FsError> {
+        read_exact_at(&self.file, offset, buffer).map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => FsError::OutOfBounds,
+            _ => FsError::Io,
+        })
+    }
+
+    fn write(&self, offset: u64, buffer: &[u8]) -> Result<(), FsError> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
+        }
+        write_all_at(&self.file, offset, buffer).map_err(|_| FsError::Io)
+    }
+
+    fn size(&self) -> Option<u64> {
+        self.file.metadata().map(|meta| meta.len()).ok()
+    }
+
+    fn flush(&self) -> Result<(), FsError> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.file.sync_data().map_err(|_| FsError::Io)
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buffer, offset)
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, offset: u64, buffer: &[u8]) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(buffer, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut offset: u64, mut buffer: &mut [u8]) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buffer.is_empty() {
+        match file.seek_read(buffer, offset)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buffer = &mut buffer[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut offset: u64, mut buffer: &[u8]) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buffer.is_empty() {
+        match file.seek_write(buffer, offset)? {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            n => {
+                buffer = &buffer[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,9 @@
+//! Storage device implementations.
+
+#[cfg(feature = "std")]
+pub mod file;
+pub mod ram;
+
+#[cfg(feature = "std")]
+pub use file::FileStorage;
+pub use ram::RamDisk;
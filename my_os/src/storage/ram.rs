@@ -0,0 +1,117 @@
+//! RAM disk storage device, usable before any disk driver exists.
+
+use crate::error::FsError;
+use crate::filesystem::StorageDevice;
+use alloc::vec::Vec;
+use core::ops::Range;
+use spin::Mutex;
+
+/// Backing memory of a RAM disk.
+enum Buffer {
+    Heap(Vec<u8>),
+    Static(&'static mut [u8]),
+}
+
+impl Buffer {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Buffer::Heap(data) => data,
+            Buffer::Static(data) => data,
+        }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        match self {
+            Buffer::Heap(data) => data,
+            Buffer::Static(data) => data,
+        }
+    }
+}
+
+/// Storage device keeping the whole volume in memory.
+pub struct RamDisk {
+    data: Mutex<Buffer>,
+}
+
+impl RamDisk {
+    /// Create a zero-filled RAM disk of `size` bytes on the heap.
+    pub fn new(size: usize) -> Self {
+        Self {
+            data: Mutex::new(Buffer::Heap(vec![0; size])),
+        }
+    }
+
+    /// Create a RAM disk on the heap holding a copy of `image`.
+    pub fn from_image(image: &[u8]) -> Self {
+        Self {
+            data: Mutex::new(Buffer::Heap(image.to_vec())),
+        }
+    }
+
+    /// Create a RAM disk over a static buffer, keeping its current contents.
+    pub fn from_static(buffer: &'static mut [u8]) -> Self {
+        Self {
+            data: Mutex::new(Buffer::Static(buffer)),
+        }
+    }
+
+    /// Copy `image` (e.g. from `include_bytes!`) into a static buffer and zero the rest.
+    /// Returns `None` if the buffer is smaller than the image.
+    pub fn with_image(buffer: &'static mut [u8], image: &[u8]) -> Option<Self> {
+        if buffer.len() < image.len() {
+            return None;
+        }
+        buffer[..image.len()].copy_from_slice(image);
+        buffer[image.len()..].fill(0);
+        Some(Self::from_static(buffer))
+    }
+
+    /// Size of the RAM disk in bytes.
+    pub fn len(&self) -> usize {
+        self.data.lock().as_slice().len()
+    }
+
+    /// Check if the RAM disk has no capacity at all.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl StorageDevice for RamDisk {
+    fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<(), FsError> {
+        let data = self.data.lock();
+        let data = data.as_slice();
+        buffer.copy_from_slice(&data[byte_range(offset, buffer.len(), data.len())?]);
+        Ok(())
+    }
+
+    fn write(&self, offset: u64, buffer: &[u8]) -> Result<(), FsError> {
+        let mut data = self.data.lock();
+        let data = data.as_mut_slice();
+        let range = byte_range(offset, buffer.len(), data.len())?;
+        data[range].copy_from_slice(buffer);
+        Ok(())
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.len() as u64)
+    }
+
+    fn discard(&self, offset: u64, len: u64) -> Result<bool, FsError> {
+        let mut data = self.data.lock();
+        let data = data.as_mut_slice();
+        let len = usize::try_from(len).map_err(|_| FsError::OutOfBounds)?;
+        let range = byte_range(offset, len, data.len())?;
+        data[range].fill(0);
+        Ok(true)
+    }
+}
+
+// Range of an access, rejected if it does not fit in `capacity`.
+fn byte_range(offset: u64, len: usize, capacity: usize) -> Result<Range<usize>, FsError> {
+    let start = usize::try_from(offset).map_err(|_| FsError::OutOfBounds)?;
+    match start.checked_add(len) {
+        Some(end) if end <= capacity => Ok(start..end),
+        _ => Err(FsError::OutOfBounds),
+    }
+}
@@ -0,0 +1,209 @@
+//! Recovery of deleted directory entries.
+//!
+//! Deleting an entry only overwrites the first byte of its name with 0xE5
+//! and frees its chain; the start cluster and size stay in the slot. If
+//! the file was contiguous and its clusters have not been reused, the data
+//! is still there. Deleted long name slots before the entry keep their
+//! characters and the checksum of the short name, which gives back the
+//! long name and usually the lost first character.
+
+use crate::directory::attribute::Attributes;
+use crate::directory::cluster::Cluster;
+use crate::directory::dir_entry::{DirectoryEntry, DirectorySlots, DELETED_ENTRY, ENTRY_SIZE};
+use crate::directory::name::decode_long_name;
+use crate::directory::table::FatValue;
+use crate::error::FsError;
+use crate::filesystem::{FatFileSystem, StorageDevice};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub use crate::directory::name::short_name_checksum;
+
+// Bit set in the ordinal of the last long name slot.
+const LAST_LONG_NAME: u8 = 0x40;
+
+/// A deleted entry found by `deleted_entries`.
+#[derive(Debug, Clone)]
+pub struct DeletedEntry {
+    pub offset: u64,               // Slot of the short entry.
+    pub entry: DirectoryEntry,     // As found, its name starting with 0xE5.
+    pub long_name: Option<String>, // Reconstructed from the deleted slots before it.
+    pub long_name_slots: Vec<u64>, // Those slots, nearest to the entry first.
+    pub first_char: Option<u8>,    // First character matching the long name checksum.
+    pub clusters: Vec<Cluster>,    // Presumed contiguous chain.
+    pub recoverable: bool,         // Every presumed cluster is still free.
+}
+
+impl DeletedEntry {
+    /// The short name with `?` in place of the lost first character.
+    pub fn file_name(&self) -> String {
+        let mut entry = self.entry;
+        entry.file_name[0] = b'?';
+        entry.file_name()
+    }
+}
+
+// Characters allowed in short names besides uppercase letters and digits.
+fn is_short_name_char(byte: u8) -> bool {
+    byte.is_ascii_uppercase() || byte.is_ascii_digit() || b"!#$%&'()-@^_`{}~".contains(&byte)
+}
+
+/// List the deleted entries of directory `dir`, in slot order.
+pub fn deleted_entries<S: StorageDevice>(
+    fs: &FatFileSystem<S>,
+    dir: Cluster,
+) -> Result<Vec<DeletedEntry>, FsError> {
+    let mut found = Vec::new();
+    let mut pending: Vec<(u64, [u8; ENTRY_SIZE])> = Vec::new(); // Deleted long name slots just seen.
+    for slot in DirectorySlots::new(fs, dir) {
+        let slot = slot?;
+        if slot.is_end() {
+            break;
+        }
+        if !slot.is_deleted() {
+            pending.clear();
+            continue;
+        }
+        if slot.is_long_name() {
+            pending.push((slot.offset, slot.bytes));
+            continue;
+        }
+
+        let entry = slot.entry();
+        let slots = core::mem::take(&mut pending);
+        if entry.is_volume_label() {
+            continue;
+        }
+        let bytes: Vec<[u8; ENTRY_SIZE]> = slots.iter().map(|&(_, bytes)| bytes).collect();
+        let (long_name, first_char, long_name_slots) = match decode_long_name(&bytes) {
+            Some((name, checksum)) => {
+                // Try the long name's initial first: it is almost always right.
+                let initial = name.chars().next().map(|c| c.to_ascii_uppercase() as u8);
+                let first_char = initial.into_iter().chain(0x21..0x7F).find(|&byte| {
+                    let mut short = entry.file_name;
+                    short[0] = byte;
+                    is_short_name_char(byte) && short_name_checksum(&short) == checksum
+                });
+                (Some(name), first_char, slots.iter().rev().map(|&(offset, _)| offset).collect())
+            }
+            None => (None, None, Vec::new()),
+        };
+        let clusters = presumed_chain(fs, &entry);
+        let recoverable = all_free(fs, &clusters)?;
+        found.push(DeletedEntry {
+            offset: slot.offset,
+            entry,
+            long_name,
+            long_name_slots,
+            first_char,
+            clusters,
+            recoverable,
+        });
+    }
+    Ok(found)
+}
+
+// The clusters a deleted entry used if its chain was contiguous.
+// A directory's size is not recorded, so only its first cluster is known.
+fn presumed_chain<S: StorageDevice>(fs: &FatFileSystem<S>, entry: &DirectoryEntry) -> Vec<Cluster> {
+    if entry.start_cluster.0 == 0 {
+        return Vec::new();
+    }
+    let count = match entry.is_directory() {
+        true => 1,
+        false => entry.file_size.div_ceil(fs.cluster_size),
+    };
+    let start = entry.start_cluster.0;
+    (start..start.saturating_add(count)).map(Cluster).collect()
+}
+
+fn all_free<S: StorageDevice>(fs: &FatFileSystem<S>, clusters: &[Cluster]) -> Result<bool, FsError> {
+    for &cluster in clusters {
+        if !fs.is_data_cluster(cluster) || FatValue::get(fs, cluster)? != FatValue::Free {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Restore a deleted entry of directory `dir` under its short name with
+/// `first_char` as the first character, returning the restored entry.
+///
+/// Fails with `InvalidArgument` if the character is not valid in a short
+/// name or the clusters are no longer free, and with `AlreadyExists` if a
+/// live entry has the restored name. Long name slots come back too when
+/// their checksum matches the restored name.
+pub fn undelete<S: StorageDevice>(
+    fs: &FatFileSystem<S>,
+    dir: Cluster,
+    deleted: &DeletedEntry,
+    first_char: char,
+) -> Result<DirectoryEntry, FsError> {
+    let _locked = fs.lock_directories(&[dir]);
+    fs.transaction(|| undelete_inner(fs, dir, deleted, first_char))
+}
+
+fn undelete_inner<S: StorageDevice>(
+    fs: &FatFileSystem<S>,
+    dir: Cluster,
+    deleted: &DeletedEntry,
+    first_char: char,
+) -> Result<DirectoryEntry, FsError> {
+    if fs.is_read_only() {
+        return Err(FsError::ReadOnly);
+    }
+    let first = first_char.to_ascii_uppercase();
+    if !first.is_ascii() || !is_short_name_char(first as u8) {
+        return Err(FsError::InvalidArgument);
+    }
+
+    // The slot must still hold the entry that was listed.
+    let mut bytes = [0u8; ENTRY_SIZE];
+    fs.read_at(deleted.offset, &mut bytes)?;
+    let current = DirectoryEntry::from_bytes(&bytes);
+    if current.file_name != deleted.entry.file_name
+        || current.start_cluster != deleted.entry.start_cluster
+        || current.file_size != deleted.entry.file_size
+    {
+        return Err(FsError::NotFound);
+    }
+    let mut entry = deleted.entry;
+    entry.file_name[0] = first as u8;
+    fs.check_absent(dir, &entry.file_name())?;
+    let clusters = presumed_chain(fs, &entry);
+    let allocation = fs.lock_allocation();
+    if !all_free(fs, &clusters)? {
+        return Err(FsError::InvalidArgument);
+    }
+
+    // 1. Take the clusters back; until the entry is restored they form a lost chain.
+    fs.claim_clusters(&clusters)?;
+    drop(allocation);
+    for pair in clusters.windows(2) {
+        FatValue::put(fs, pair[0], FatValue::Data(pair[1].0))?;
+    }
+    fs.barrier()?;
+
+    // 2. Revive the long name slots, then the entry itself.
+    let checksum_matches = deleted.long_name.is_some()
+        && deleted.long_name_slots.iter().all(|&offset| {
+            let mut slot = [0u8; ENTRY_SIZE];
+            fs.read_at(offset, &mut slot).is_ok()
+                && slot[0] == DELETED_ENTRY
+                && Attributes::new(slot[11]).is_long_name()
+                && slot[13] == short_name_checksum(&entry.file_name)
+        });
+    if checksum_matches {
+        let count = deleted.long_name_slots.len();
+        for (index, &offset) in deleted.long_name_slots.iter().enumerate() {
+            let mut ordinal = index as u8 + 1;
+            if index + 1 == count {
+                ordinal |= LAST_LONG_NAME;
+            }
+            fs.write_at(offset, &[ordinal])?;
+        }
+    }
+    fs.write_at(deleted.offset, &[first as u8])?;
+    fs.barrier()?;
+    Ok(entry)
+}
@@ -0,0 +1,168 @@
+//! Volume information: statistics, serial number and label.
+
+use crate::boot_sector::BootSector;
+use crate::directory::attribute::Attributes;
+use crate::directory::cluster::Cluster;
+use crate::directory::dir_entry::{DirectoryEntry, DirectorySlots, DELETED_ENTRY};
+use crate::directory::table::FatValue;
+use crate::error::FsError;
+use crate::filesystem::{FatFileSystem, StorageDevice};
+use crate::mkfs::NO_NAME;
+use alloc::string::{String, ToString};
+
+/// FAT variant, determined by the number of data clusters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// Classify a volume the way Microsoft's specification does.
+    pub fn from_cluster_count(cluster_count: u32) -> Self {
+        match cluster_count {
+            0..=4084 => FatType::Fat12,
+            4085..=65524 => FatType::Fat16,
+            _ => FatType::Fat32,
+        }
+    }
+
+    /// Width of a FAT entry in bits (FAT32 uses only the low 28 of its 32).
+    pub fn entry_bits(self) -> u32 {
+        match self {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 28,
+        }
+    }
+
+    /// Mask of the meaningful bits of a FAT entry.
+    pub fn entry_mask(self) -> u32 {
+        (1 << self.entry_bits()) - 1
+    }
+}
+
+/// Usage and identification of a mounted volume.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolumeStats {
+    pub fat_type: FatType,
+    pub oem_name: String,
+    pub volume_id: u32,        // Serial number.
+    pub label: Option<String>, // Root-directory label, else the boot sector one.
+    pub cluster_size: u32,
+    pub total_clusters: u32,
+    pub free_clusters: u32,
+    pub used_clusters: u32, // Includes bad clusters.
+}
+
+impl VolumeStats {
+    pub fn total_bytes(&self) -> u64 {
+        self.total_clusters as u64 * self.cluster_size as u64
+    }
+
+    pub fn free_bytes(&self) -> u64 {
+        self.free_clusters as u64 * self.cluster_size as u64
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.used_clusters as u64 * self.cluster_size as u64
+    }
+}
+
+// Printable form of a space-padded label, `None` if unset.
+fn label_text(raw: &[u8; 11]) -> Option<String> {
+    if raw == &NO_NAME {
+        return None;
+    }
+    let text = String::from_utf8_lossy(raw).trim_end_matches(' ').to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+// Upper-case and pad a label, rejecting characters DOS does not allow.
+fn encode_label(label: &str) -> Result<[u8; 11], FsError> {
+    let valid = |byte: u8| (0x20..0x7F).contains(&byte) && !b"\"*+,./:;<=>?[\\]|".contains(&byte);
+    if label.len() > 11 || !label.bytes().all(valid) {
+        return Err(FsError::InvalidArgument);
+    }
+    let mut raw = [b' '; 11];
+    for (dst, src) in raw.iter_mut().zip(label.bytes()) {
+        *dst = src.to_ascii_uppercase();
+    }
+    Ok(raw)
+}
+
+impl<S: StorageDevice> FatFileSystem<S> {
+    /// Read the boot sector of the volume.
+    pub fn boot_sector(&self) -> Result<BootSector, FsError> {
+        let mut sector = [0u8; 512];
+        self.read_at(0, &mut sector)?;
+        BootSector::parse(&sector)
+    }
+
+    // The volume-label entry of the root directory and its offset, if there is one.
+    fn label_entry(&self) -> Result<Option<(DirectoryEntry, u64)>, FsError> {
+        for slot in DirectorySlots::new(self, self.root_cluster) {
+            let slot = slot?;
+            if slot.is_end() {
+                break;
+            }
+            if !slot.is_deleted() && !slot.is_long_name() && slot.entry().is_volume_label() {
+                return Ok(Some((slot.entry(), slot.offset)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Count free clusters and collect the volume identification.
+    pub fn stats(&self) -> Result<VolumeStats, FsError> {
+        let boot = self.boot_sector()?;
+        let table = FatValue::load_table(self, 0)?;
+        let free_clusters = table[2..].iter().filter(|&&raw| raw == 0).count() as u32;
+
+        let root_label = self.label_entry()?.and_then(|(entry, _)| label_text(&entry.file_name));
+        Ok(VolumeStats {
+            fat_type: self.fat_type,
+            oem_name: String::from_utf8_lossy(&boot.oem_name).trim_end().to_string(),
+            volume_id: boot.volume_id,
+            label: root_label.or_else(|| label_text(&boot.volume_label)),
+            cluster_size: self.cluster_size,
+            total_clusters: self.cluster_count,
+            free_clusters,
+            used_clusters: self.cluster_count - free_clusters,
+        })
+    }
+
+    /// Set the volume label in the root directory and the boot sector (and its backup).
+    /// An empty label removes it.
+    pub fn set_label(&self, label: &str) -> Result<(), FsError> {
+        let raw = if label.is_empty() { NO_NAME } else { encode_label(label)? };
+        let boot = self.boot_sector()?;
+        let _locked = self.lock_directories(&[self.root_cluster]);
+        self.transaction(|| {
+            match (self.label_entry()?, raw == NO_NAME) {
+                (Some((_, offset)), true) => self.write_at(offset, &[DELETED_ENTRY])?,
+                (Some((_, offset)), false) => self.write_at(offset, &raw)?,
+                (None, true) => {}
+                (None, false) => {
+                    let entry = DirectoryEntry {
+                        file_name: raw,
+                        attributes: Attributes::VOLUME_ID,
+                        start_cluster: Cluster(0),
+                        file_size: 0,
+                    };
+                    self.add_entry_inner(self.root_cluster, &entry)?;
+                }
+            }
+
+            let label_offset = boot.label_offset() as u64;
+            self.write_at(label_offset, &raw)?;
+            if boot.fat_size_16 == 0 && boot.backup_boot_sector != 0 {
+                let backup = boot.backup_boot_sector as u64 * boot.bytes_per_sector as u64;
+                self.write_at(backup + label_offset, &raw)?;
+            }
+            Ok(())
+        })?;
+        self.barrier()
+    }
+}
@@ -0,0 +1,95 @@
+//! Erasing deleted data.
+//!
+//! Freed clusters and deleted directory slots keep their old contents until
+//! reused. `remove_with` can erase a file's clusters as it deletes it, and
+//! `wipe_free_space` erases whatever earlier deletions left behind. Ranges
+//! are discarded where the device supports it and overwritten with zeros
+//! otherwise.
+
+use crate::directory::cluster::Cluster;
+use crate::directory::dir_entry::{DirectorySlots, DELETED_ENTRY, ENTRY_SIZE};
+use crate::directory::table::FatValue;
+use crate::error::FsError;
+use crate::filesystem::{FatFileSystem, StorageDevice};
+use alloc::vec::Vec;
+
+/// Result of `FatFileSystem::wipe_free_space`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WipeReport {
+    pub clusters: u32,   // Free clusters erased.
+    pub slots: u32,      // Deleted directory slots cleared.
+    pub discarded: bool, // The device discarded the clusters instead of having zeros written.
+}
+
+impl<S: StorageDevice> FatFileSystem<S> {
+    /// Erase clusters, one device request per run of consecutive clusters.
+    pub(crate) fn erase_clusters(&self, clusters: &[Cluster]) -> Result<bool, FsError> {
+        let mut discarded = true;
+        let mut rest = clusters;
+        while let Some(&first) = rest.first() {
+            let run = rest
+                .iter()
+                .enumerate()
+                .take_while(|&(index, cluster)| cluster.0 == first.0 + index as u32)
+                .count();
+            let len = run as u64 * self.cluster_size as u64;
+            discarded &= self.erase(self.cluster_offset(first), len)?;
+            rest = &rest[run..];
+        }
+        Ok(discarded)
+    }
+
+    /// Erase every free cluster and clear the deleted slots of every directory,
+    /// so that no deleted data can be recovered.
+    ///
+    /// Deleted slots keep only their 0xE5 marker, which keeps later entries
+    /// of the directory reachable.
+    pub fn wipe_free_space(&self) -> Result<WipeReport, FsError> {
+        if self.is_read_only() {
+            return Err(FsError::ReadOnly);
+        }
+        // No cluster may be allocated and written while it is being erased.
+        let allocation = self.lock_allocation();
+        let fat = FatValue::load_table(self, 0)?;
+        let free: Vec<Cluster> = (2..fat.len() as u32)
+            .filter(|&cluster| fat[cluster as usize] == 0)
+            .map(Cluster)
+            .collect();
+        let mut report = WipeReport {
+            clusters: free.len() as u32,
+            discarded: !free.is_empty() && self.erase_clusters(&free)?,
+            ..Default::default()
+        };
+        drop(allocation);
+
+        // Collect the directories first: clearing slots rewrites them.
+        let mut directories = Vec::from([self.root_cluster]);
+        for item in self.walk("/")? {
+            let (_, entry) = item?;
+            if entry.is_directory() {
+                directories.push(self.directory_cluster(&entry));
+            }
+        }
+        let mut cleared = [0u8; ENTRY_SIZE];
+        cleared[0] = DELETED_ENTRY;
+        for dir in directories {
+            let _locked = self.lock_directories(&[dir]);
+            report.slots += self.transaction(|| {
+                let mut slots = 0;
+                for slot in DirectorySlots::new(self, dir) {
+                    let slot = slot?;
+                    if slot.is_end() {
+                        break;
+                    }
+                    if slot.is_deleted() && slot.bytes != cleared {
+                        self.write_at(slot.offset, &cleared)?;
+                        slots += 1;
+                    }
+                }
+                Ok(slots)
+            })?;
+        }
+        self.barrier()?;
+        Ok(report)
+    }
+}